@@ -10,6 +10,9 @@ use std::path::Path;
 pub struct Assets<'a> {
     pub textures: Textures<'a>,
     pub chunks: Chunks,
+    /// Background of the current song, drawn in place of `textures.background` if present
+    pub song_background: Option<Texture<'a>>,
+    texture_creator: &'a TextureCreator<WindowContext>,
 }
 
 pub struct Textures<'a> {
@@ -25,6 +28,12 @@ pub struct Textures<'a> {
     pub judge_text_good: Texture<'a>,
     pub judge_text_ok: Texture<'a>,
     pub judge_text_bad: Texture<'a>,
+    /// `img/drum_don.png` and `img/drum_ka.png`, optional; the drum does not light up on hits
+    /// without them
+    pub drum_don: Option<Texture<'a>>,
+    pub drum_ka: Option<Texture<'a>>,
+    /// `img/renda_badge.png`, optional; only the hit count is drawn without it
+    pub renda_badge: Option<Texture<'a>>,
     pub combo_nummber_white: Vec<Texture<'a>>,
     pub combo_nummber_silver: Vec<Texture<'a>>,
     pub combo_nummber_gold: Vec<Texture<'a>>,
@@ -41,29 +50,39 @@ pub struct Textures<'a> {
 pub struct Chunks {
     pub sound_don: SoundBuffer,
     pub sound_ka: SoundBuffer,
+    /// `snd/balloon.ogg`, optional; balloons burst silently without it
+    pub sound_balloon: Option<SoundBuffer>,
 }
 
 impl<'a> Assets<'a> {
     pub fn new<'b>(
         texture_creator: &'a TextureCreator<WindowContext>,
         audio_manager: &'b AudioManager<AutoEvent>, // TODO should be stream_config instead
+        assets_dir: &Path,
+        high_contrast_notes: bool,
     ) -> Result<Assets<'a>, TaikoError> {
-        let assets_dir = Path::new("assets");
-
         let img_dir = assets_dir.join("img");
+        // The images colored by don and ka are replaced with `*_high_contrast.png` if requested
+        let palette_image = |name: &str| {
+            if high_contrast_notes {
+                img_dir.join(format!("{}_high_contrast.png", name))
+            } else {
+                img_dir.join(format!("{}.png", name))
+            }
+        };
         let tc = texture_creator;
         let textures = Textures {
             background: load_texture_and_check_size(tc, img_dir.join("game_bg.png"), (1920, 1080))?,
-            note_don: load_texture_and_check_size(tc, img_dir.join("note_don.png"), (195, 195))?,
-            note_ka: load_texture_and_check_size(tc, img_dir.join("note_ka.png"), (195, 195))?,
+            note_don: load_texture_and_check_size(tc, palette_image("note_don"), (195, 195))?,
+            note_ka: load_texture_and_check_size(tc, palette_image("note_ka"), (195, 195))?,
             note_don_large: load_texture_and_check_size(
                 tc,
-                img_dir.join("note_don_large.png"),
+                palette_image("note_don_large"),
                 (195, 195),
             )?,
             note_ka_large: load_texture_and_check_size(
                 tc,
-                img_dir.join("note_ka_large.png"),
+                palette_image("note_ka_large"),
                 (195, 195),
             )?,
             renda_left: load_texture_and_check_size(
@@ -101,6 +120,18 @@ impl<'a> Assets<'a> {
                 img_dir.join("judge_text_bad.png"),
                 (135, 90),
             )?,
+            drum_don: load_optional(
+                "drum_don",
+                load_texture_and_check_size(tc, palette_image("drum_don"), (180, 180)),
+            ),
+            drum_ka: load_optional(
+                "drum_ka",
+                load_texture_and_check_size(tc, palette_image("drum_ka"), (180, 180)),
+            ),
+            renda_badge: load_optional(
+                "renda_badge",
+                load_texture_and_check_size(tc, img_dir.join("renda_badge.png"), (240, 120)),
+            ),
             combo_nummber_white: load_combo_textures(|i| {
                 tc.load_texture(img_dir.join(format!("combo_number_white_{}.png", i)))
             })?,
@@ -154,9 +185,29 @@ impl<'a> Assets<'a> {
         let chunks = Chunks {
             sound_don: load_sound("dong.ogg")?,
             sound_ka: load_sound("ka.ogg")?,
+            sound_balloon: load_optional("balloon.ogg", load_sound("balloon.ogg")),
         };
 
-        Ok(Assets { textures, chunks })
+        Ok(Assets {
+            textures,
+            chunks,
+            song_background: None,
+            texture_creator,
+        })
+    }
+
+    /// Load the background of a song.  Any size is accepted, as it is stretched to the playfield.
+    /// The default background is used if `path` is `None` or the image cannot be loaded.
+    pub fn set_song_background(&mut self, path: Option<&Path>) {
+        self.song_background = path.and_then(|path| {
+            self.texture_creator
+                .load_texture(path)
+                .map_err(|e| {
+                    println!("Failed to load background image {:?}", path);
+                    println!("Caused by: {:?}", e);
+                })
+                .ok()
+        });
     }
 }
 
@@ -191,3 +242,16 @@ where
         .collect::<Result<_, _>>()
         .map_err(|s| new_sdl_error("Failed to load a texture", s))
 }
+
+/// Returns the asset if it has been loaded, or reports the failure and returns `None`.  Used
+/// for the assets added later, so that an older assets directory keeps working.
+fn load_optional<T>(name: &str, result: Result<T, TaikoError>) -> Option<T> {
+    match result {
+        Ok(asset) => Some(asset),
+        Err(e) => {
+            println!("Failed to load {}; it will be omitted", name);
+            println!("Caused by: {:?}", e);
+            None
+        }
+    }
+}
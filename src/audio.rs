@@ -1,12 +1,16 @@
 use crate::errors::{CpalOrRodioError, TaikoError, TaikoErrorCause};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{ChannelCount, SampleFormat, SampleRate, SizedSample, Stream, StreamConfig};
+use cpal::{
+    ChannelCount, Sample as _, SampleFormat, SampleRate, SizedSample, Stream, StreamConfig,
+};
+use hound::{WavSpec, WavWriter};
 use itertools::Itertools;
 use rodio::source::UniformSourceIterator;
 use rodio::{Decoder, Source};
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, BufWriter};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{mpsc, Arc, Mutex, Weak};
@@ -16,6 +20,7 @@ use universal_audio_decoder::{new_uniform_source_iterator, TrueUniformSourceIter
 
 pub struct AudioManager<T> {
     pub stream_config: StreamConfig,
+    pub sample_format: SampleFormat,
     sender_to_audio: Sender<MessageToAudio<T>>,
     drop_sender: Sender<()>,
     pub sound_effect_receiver: Receiver<T>,
@@ -26,9 +31,11 @@ enum PlaybackPosition {
     NotStarted,
     Seeking {
         music_position: f64,
+        play_speed: f64,
     },
     Paused {
         music_position: f64,
+        play_speed: f64,
     },
     Playing {
         instant: Instant,
@@ -41,10 +48,16 @@ enum MessageToAudio<T> {
     Play,
     Pause,
     Seek(f64),
-    LoadMusic(PathBuf),
+    /// The result is sent back, since the file is opened in the audio thread
+    LoadMusic(PathBuf, mpsc::Sender<Result<(), TaikoError>>),
+    SetMusic(PreloadedMusic),
+    UnloadMusic,
+    StartRecording(MixRecorder),
+    StopRecording,
     AddPlay(SoundBufferSource),
 
     SetMusicVolume(f32),
+    SetClipThreshold(f32),
     SetPlaySpeed(f64),
 
     AddSchedules(Vec<SoundEffectSchedule<T>>),
@@ -72,8 +85,11 @@ impl<T: Send + 'static> AudioManager<T> {
                         eprintln!("Failed to send error info to main thread.");
                     }
                 }
-                Ok((stream_config, _stream)) => {
-                    if stream_config_sender.send(Ok(stream_config)).is_err() {
+                Ok((stream_config, sample_format, _stream)) => {
+                    if stream_config_sender
+                        .send(Ok((stream_config, sample_format)))
+                        .is_err()
+                    {
                         eprintln!("Failed to send stream config to main thread.");
                     }
                     // preserve stream until "drop" signal is sent from main thread
@@ -81,13 +97,15 @@ impl<T: Send + 'static> AudioManager<T> {
                 }
             }
         });
-        let stream_config = stream_config_receiver.recv().map_err(|_| TaikoError {
-            message: "Audio device initialization thread has been stopped".to_string(),
-            cause: TaikoErrorCause::None,
-        })??;
+        let (stream_config, sample_format) =
+            stream_config_receiver.recv().map_err(|_| TaikoError {
+                message: "Audio device initialization thread has been stopped".to_string(),
+                cause: TaikoErrorCause::None,
+            })??;
 
         Ok(AudioManager {
             stream_config,
+            sample_format,
             sender_to_audio,
             drop_sender,
             sound_effect_receiver,
@@ -95,18 +113,65 @@ impl<T: Send + 'static> AudioManager<T> {
         })
     }
 
+    /// Load the music to stream, waiting until the audio thread opens it.  If this fails, the
+    /// previous music is unloaded, and silence is played instead.
     pub fn load_music<P>(&self, path: P) -> Result<(), TaikoError>
     where
         P: Into<PathBuf>,
     {
+        let (result_sender, result_receiver) = mpsc::channel();
+        let stopped = || TaikoError {
+            message: "Failed to load music; the audio stream has been stopped".to_string(),
+            cause: TaikoErrorCause::None,
+        };
+        self.sender_to_audio
+            .send(MessageToAudio::LoadMusic(path.into(), result_sender))
+            .map_err(|_| stopped())?;
+        result_receiver.recv().map_err(|_| stopped())?
+    }
+
+    /// Start decoding the whole music on a worker thread, so that `play` does not have to wait
+    /// for the decoder.  The decoded music is sent to the returned receiver, and is played once
+    /// it is passed to `set_music`.
+    pub fn preload_music<P>(
+        &self,
+        path: P,
+        quality: ResampleQuality,
+    ) -> Receiver<Result<PreloadedMusic, TaikoError>>
+    where
+        P: Into<PathBuf>,
+    {
+        let (music_sender, music_receiver) = mpsc::channel();
+        let path = path.into();
+        let stream_config = self.stream_config.clone();
+        thread::spawn(move || {
+            let music = PreloadedMusic::load(path, &stream_config, quality);
+            // The receiver is gone if the game has been closed while decoding
+            music_sender.send(music).ok();
+        });
+        music_receiver
+    }
+
+    /// Play the music decoded by `preload_music` in place of the music loaded so far.
+    pub fn set_music(&self, music: PreloadedMusic) -> Result<(), TaikoError> {
         self.sender_to_audio
-            .send(MessageToAudio::LoadMusic(path.into()))
+            .send(MessageToAudio::SetMusic(music))
             .map_err(|_| TaikoError {
                 message: "Failed to load music; the audio stream has been stopped".to_string(),
                 cause: TaikoErrorCause::None,
             })
     }
 
+    /// Play silence in place of the music loaded so far.
+    pub fn unload_music(&self) -> Result<(), TaikoError> {
+        self.sender_to_audio
+            .send(MessageToAudio::UnloadMusic)
+            .map_err(|_| TaikoError {
+                message: "Failed to unload music; the audio stream has been stopped".to_string(),
+                cause: TaikoErrorCause::None,
+            })
+    }
+
     pub fn play(&self) -> Result<(), TaikoError> {
         self.sender_to_audio
             .send(MessageToAudio::Play)
@@ -135,6 +200,7 @@ impl<T: Send + 'static> AudioManager<T> {
             })?;
             *playback_position = PlaybackPosition::Seeking {
                 music_position: time,
+                play_speed: playback_position.play_speed(),
             };
         }
         self.sender_to_audio
@@ -167,6 +233,17 @@ impl<T: Send + 'static> AudioManager<T> {
             })
     }
 
+    /// Limit the amplitude of the music to `threshold`, which must be positive.
+    pub fn set_clip_threshold(&self, threshold: f32) -> Result<(), TaikoError> {
+        self.sender_to_audio
+            .send(MessageToAudio::SetClipThreshold(threshold))
+            .map_err(|_| TaikoError {
+                message: "Failed to set clip threshold; the audio stream has been stopped"
+                    .to_string(),
+                cause: TaikoErrorCause::None,
+            })
+    }
+
     pub fn set_play_speed(&self, speed: f64) -> Result<(), TaikoError> {
         self.sender_to_audio
             .send(MessageToAudio::SetPlaySpeed(speed))
@@ -176,9 +253,37 @@ impl<T: Send + 'static> AudioManager<T> {
             })
     }
 
+    /// Start writing the mixed output to a WAV file, replacing the recording in progress if any.
+    pub fn start_recording<P>(&self, path: P) -> Result<(), TaikoError>
+    where
+        P: AsRef<Path>,
+    {
+        let recorder = MixRecorder::create(path, &self.stream_config, self.sample_format)?;
+        self.sender_to_audio
+            .send(MessageToAudio::StartRecording(recorder))
+            .map_err(|_| TaikoError {
+                message: "Failed to start recording; the audio stream has been stopped".to_string(),
+                cause: TaikoErrorCause::None,
+            })
+    }
+
+    pub fn stop_recording(&self) -> Result<(), TaikoError> {
+        self.sender_to_audio
+            .send(MessageToAudio::StopRecording)
+            .map_err(|_| TaikoError {
+                message: "Failed to stop recording; the audio stream has been stopped".to_string(),
+                cause: TaikoErrorCause::None,
+            })
+    }
+
     pub fn add_play(&self, buffer: &SoundBuffer) -> Result<(), TaikoError> {
+        self.add_play_with_pan(buffer, 0.0)
+    }
+
+    /// Play `buffer` at `pan`, from -1 (left) to 1 (right).
+    pub fn add_play_with_pan(&self, buffer: &SoundBuffer, pan: f32) -> Result<(), TaikoError> {
         self.sender_to_audio
-            .send(MessageToAudio::AddPlay(buffer.new_source()))
+            .send(MessageToAudio::AddPlay(buffer.new_source().with_pan(pan)))
             .map_err(|_| TaikoError {
                 message: "Failed to play a chunk; the audio stream has been stopped".to_string(),
                 cause: TaikoErrorCause::None,
@@ -223,8 +328,26 @@ impl<T: Send + 'static> AudioManager<T> {
                 .to_string(),
             cause: TaikoErrorCause::None,
         })?;
+        Ok(playback_position.music_position())
+    }
+
+    /// Returns the music position, the play speed and whether the music is playing, all read
+    /// at once so that they are consistent with each other.
+    /// Returns error only if the audio stream has been pannicked.
+    pub fn playback_state(&self) -> Result<Option<(f64, f64, bool)>, TaikoError> {
+        let playback_position = self.playback_position.lock().map_err(|_| TaikoError {
+            message: "Failed to obtain playback state; the audio stream has been panicked"
+                .to_string(),
+            cause: TaikoErrorCause::None,
+        })?;
+        Ok(playback_position.playback_state())
+    }
+}
+
+impl PlaybackPosition {
+    fn music_position(&self) -> Option<f64> {
         use PlaybackPosition::*;
-        let res = match *playback_position {
+        match *self {
             Playing {
                 music_position,
                 instant,
@@ -238,10 +361,25 @@ impl<T: Send + 'static> AudioManager<T> {
                 };
                 Some(music_position + diff)
             }
-            Paused { music_position } | Seeking { music_position } => Some(music_position),
+            Paused { music_position, .. } | Seeking { music_position, .. } => Some(music_position),
             NotStarted => None,
-        };
-        Ok(res)
+        }
+    }
+
+    fn play_speed(&self) -> f64 {
+        use PlaybackPosition::*;
+        match *self {
+            Playing { play_speed, .. } | Paused { play_speed, .. } | Seeking { play_speed, .. } => {
+                play_speed
+            }
+            NotStarted => 1.0,
+        }
+    }
+
+    fn playback_state(&self) -> Option<(f64, f64, bool)> {
+        let playing = matches!(self, PlaybackPosition::Playing { .. });
+        self.music_position()
+            .map(|music_position| (music_position, self.play_speed(), playing))
     }
 }
 
@@ -249,7 +387,7 @@ fn stream_thread<T: Send + 'static>(
     receiver_to_audio: Receiver<MessageToAudio<T>>,
     sound_effect_sender: Sender<T>,
     playback_position_ptr: Weak<Mutex<PlaybackPosition>>,
-) -> Result<(StreamConfig, Stream), TaikoError> {
+) -> Result<(StreamConfig, SampleFormat, Stream), TaikoError> {
     let host = cpal::default_host();
     let device = host.default_output_device().ok_or_else(|| TaikoError {
         message: "No default audio output device is available".to_string(),
@@ -358,7 +496,7 @@ fn stream_thread<T: Send + 'static>(
         message: "Failed to play the audio output stream".to_string(),
         cause: TaikoErrorCause::CpalOrRodioError(CpalOrRodioError::PlayStreamError(e)),
     })?;
-    Ok((stream_config, stream))
+    Ok((stream_config, sample_format, stream))
 }
 
 impl<T> Drop for AudioManager<T> {
@@ -369,7 +507,137 @@ impl<T> Drop for AudioManager<T> {
     }
 }
 
-type MusicSource = TrueUniformSourceIterator<Decoder<BufReader<File>>>;
+enum MusicSource {
+    Streaming {
+        source: TrueUniformSourceIterator<Decoder<BufReader<File>>>,
+        /// Not every decoder knows the duration in advance
+        duration: Option<f64>,
+    },
+    Preloaded(PreloadedMusic),
+}
+
+impl MusicSource {
+    fn seek(&mut self, time: f64) -> Result<u64, String> {
+        match self {
+            MusicSource::Streaming { source, .. } => source.seek(time),
+            MusicSource::Preloaded(music) => Ok(music.seek(time)),
+        }
+    }
+
+    fn set_output_sample_rate(&mut self, sample_rate: f64) {
+        match self {
+            MusicSource::Streaming { source, .. } => source.set_output_sample_rate(sample_rate),
+            MusicSource::Preloaded(music) => music.output_sample_rate = sample_rate,
+        }
+    }
+
+    fn duration(&self) -> Option<f64> {
+        match self {
+            MusicSource::Streaming { duration, .. } => *duration,
+            MusicSource::Preloaded(music) => {
+                Some((music.data.len() / music.channels) as f64 / music.sample_rate)
+            }
+        }
+    }
+}
+
+impl Iterator for MusicSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            MusicSource::Streaming { source, .. } => source.next(),
+            MusicSource::Preloaded(music) => music.next(),
+        }
+    }
+}
+
+/// How the preloaded music is resampled when played at a different speed.  The streaming music
+/// is resampled by the decoder, whose algorithm is fixed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResampleQuality {
+    /// Interpolate between the two nearest frames
+    Linear,
+    /// Catmull-Rom interpolation among the four nearest frames, which sounds smoother but costs
+    /// about twice as much CPU time as `Linear`
+    Cubic,
+}
+
+/// Music decoded in advance into the channels and the sample rate of the stream.
+pub struct PreloadedMusic {
+    data: Arc<Vec<f32>>,
+    quality: ResampleQuality,
+    channels: usize,
+    sample_rate: f64,
+    output_sample_rate: f64,
+    /// Position in frames, which advances by `sample_rate / output_sample_rate` per output frame
+    position: f64,
+    channel: usize,
+}
+
+impl PreloadedMusic {
+    fn load(
+        path: PathBuf,
+        stream_config: &StreamConfig,
+        quality: ResampleQuality,
+    ) -> Result<PreloadedMusic, TaikoError> {
+        let data = decode_file(
+            path,
+            stream_config.channels,
+            stream_config.sample_rate,
+            "music file",
+        )?;
+        let sample_rate = stream_config.sample_rate.0 as f64;
+        Ok(PreloadedMusic {
+            data: Arc::new(data),
+            quality,
+            channels: stream_config.channels as usize,
+            sample_rate,
+            output_sample_rate: sample_rate,
+            position: 0.0,
+            channel: 0,
+        })
+    }
+
+    /// Returns the number of output frames corresponding to `time`.
+    fn seek(&mut self, time: f64) -> u64 {
+        self.position = time * self.sample_rate;
+        self.channel = 0;
+        (time * self.output_sample_rate) as u64
+    }
+}
+
+impl Iterator for PreloadedMusic {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = self.position as usize;
+        let fraction = (self.position - frame as f64) as f32;
+        let sample = |frame: usize| self.data.get(frame * self.channels + self.channel).copied();
+        let p1 = sample(frame)?;
+        let p2 = sample(frame + 1).unwrap_or(p1);
+        let ret = match self.quality {
+            ResampleQuality::Linear => p1 + (p2 - p1) * fraction,
+            ResampleQuality::Cubic => {
+                let p0 = frame.checked_sub(1).and_then(sample).unwrap_or(p1);
+                let p3 = sample(frame + 2).unwrap_or(p2);
+                let t = fraction;
+                p1 + 0.5
+                    * t
+                    * (p2 - p0
+                        + t * (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3
+                            + t * (3.0 * (p1 - p2) + p3 - p0)))
+            }
+        };
+        self.channel += 1;
+        if self.channel == self.channels {
+            self.channel = 0;
+            self.position += self.sample_rate / self.output_sample_rate;
+        }
+        Some(ret)
+    }
+}
 
 struct AudioThreadState<T> {
     stream_config: StreamConfig,
@@ -382,11 +650,14 @@ struct AudioThreadState<T> {
 
     receiver_to_audio: Receiver<MessageToAudio<T>>,
     sound_effect_sender: Sender<T>,
+    recorder: Option<MixRecorder>,
     playing: bool,
     played_sample_count: usize,
     skip_sample_count: usize,
     playback_position_ptr: Weak<Mutex<PlaybackPosition>>,
     music_volume: f32,
+    /// The music is clamped into `[-clip_threshold, clip_threshold]` to prevent too large sound
+    clip_threshold: f32,
     play_speed: f64,
 }
 
@@ -394,6 +665,8 @@ pub struct SoundEffectSchedule<T> {
     pub timestamp: f64,
     pub source: SoundBufferSource,
     pub volume: f64,
+    /// From -1 (left) to 1 (right)
+    pub pan: f32,
     pub response: T,
 }
 
@@ -414,11 +687,13 @@ impl<T> AudioThreadState<T> {
 
             receiver_to_audio,
             sound_effect_sender,
+            recorder: None,
             playing: false,
             played_sample_count: 0,
             skip_sample_count: 0,
             playback_position_ptr,
             music_volume: 1.0,
+            clip_threshold: 4.0,
             play_speed: 1.0,
         }
     }
@@ -428,72 +703,7 @@ impl<T> AudioThreadState<T> {
         S: SizedSample + cpal::FromSample<f32>,
     {
         move |output, callback_info| {
-            for message in self.receiver_to_audio.try_iter() {
-                match message {
-                    MessageToAudio::Play => self.playing = true,
-                    MessageToAudio::Pause => {
-                        self.playing = false;
-                        self.update_pause_state();
-                    }
-                    MessageToAudio::Seek(time) => {
-                        // TODO refactoring
-                        if let Err(e) = if let (Some(music), false) = (&mut self.music, false) {
-                            match music.seek(time.max(0.0)).map_err(|e| TaikoError {
-                                message: e,
-                                cause: TaikoErrorCause::None,
-                            }) {
-                                Ok(sample_count) => {
-                                    self.skip_sample_count = (-time.min(0.0)
-                                        * self.stream_config.sample_rate.0 as f64
-                                        / self.play_speed)
-                                        as usize
-                                        * (self.stream_config.channels as usize);
-                                    self.played_sample_count = sample_count as usize;
-                                    self.update_pause_state();
-                                    Ok(())
-                                }
-                                Err(e) => Err(e),
-                            }
-                        } else {
-                            Err(TaikoError {
-                                message: "Music is empty or playing".to_owned(),
-                                cause: TaikoErrorCause::None,
-                            })
-                        } {
-                            println!("Failed to seek: {:?}", e);
-                        }
-                    }
-                    MessageToAudio::LoadMusic(path) => {
-                        // TODO send error via another channel
-                        self.music = Some(self.load_music(path).unwrap())
-                    }
-                    MessageToAudio::SetMusicVolume(volume) => self.music_volume = volume,
-                    MessageToAudio::SetPlaySpeed(speed) => {
-                        self.play_speed = speed;
-                        if let Some(music) = &mut self.music {
-                            music.set_output_sample_rate(
-                                self.stream_config.sample_rate.0 as f64 / speed,
-                            );
-                        };
-                    }
-                    MessageToAudio::AddPlay(source) => {
-                        self.sound_effects.push(source);
-                    }
-                    MessageToAudio::CleanSchedules => {
-                        self.sound_effect_schedules.clear();
-                    }
-                    MessageToAudio::AddSchedules(mut schedules) => {
-                        schedules.sort_unstable_by(|x, y| {
-                            x.timestamp.partial_cmp(&y.timestamp).unwrap()
-                        });
-                        // TODO check for time rollback
-                        self.sound_effect_schedules.extend(schedules.into_iter());
-                    }
-                    MessageToAudio::SwitchScheduled(enabled) => {
-                        self.scheduled_play_enabled = enabled;
-                    }
-                }
-            }
+            self.receive_messages();
 
             if self.playing {
                 let timestamp = callback_info.timestamp();
@@ -530,50 +740,164 @@ impl<T> AudioThreadState<T> {
                     if next.timestamp < music_position_start || !self.scheduled_play_enabled {
                         continue;
                     }
-                    let mut source = next.source;
-                    source.wait = (self.stream_config.channels as f64
-                        * (next.timestamp - music_position_start)
+                    let mut source = next.source.with_pan(next.pan);
+                    // Wait for whole frames so that the pan applies to the right channels
+                    source.wait = ((next.timestamp - music_position_start)
                         * self.stream_config.sample_rate.0 as f64
-                        * self.play_speed) as usize;
+                        * self.play_speed) as usize
+                        * (self.stream_config.channels as usize);
                     self.sound_effects.push(source);
                     self.sound_effect_sender
                         .send(next.response)
                         .map_err(|e| format!("The main thread has been panicked: {}", e))
                         .unwrap(); // Intentionally panic when error
                 }
-
-                // TODO: SPAGHETTI CODE!
-                self.played_sample_count += output.len().saturating_sub(self.skip_sample_count)
-                    / (self.stream_config.channels as usize)
             }
 
-            for out in output.iter_mut() {
-                let mut next = match &mut self.music {
-                    Some(music) if self.playing => {
-                        if self.skip_sample_count > 0 {
-                            self.skip_sample_count -= 1;
-                            None
-                        } else {
-                            music.next().map(|a| a * self.music_volume)
+            self.mix(output);
+        }
+    }
+
+    fn receive_messages(&mut self) {
+        while let Ok(message) = self.receiver_to_audio.try_recv() {
+            match message {
+                MessageToAudio::Play => self.playing = true,
+                MessageToAudio::Pause => {
+                    self.playing = false;
+                    self.update_pause_state();
+                }
+                MessageToAudio::Seek(time) => {
+                    if let Err(e) = self.seek(time) {
+                        println!("Failed to seek: {:?}", e);
+                    }
+                }
+                MessageToAudio::LoadMusic(path, result_sender) => {
+                    let result = self.load_music(path).map(|music| self.music = Some(music));
+                    if result.is_err() {
+                        self.music = None;
+                    }
+                    // The main thread may have stopped waiting
+                    result_sender.send(result).ok();
+                }
+                MessageToAudio::SetMusic(music) => {
+                    let mut music = MusicSource::Preloaded(music);
+                    music.set_output_sample_rate(
+                        self.stream_config.sample_rate.0 as f64 / self.play_speed,
+                    );
+                    self.music = Some(music);
+                }
+                MessageToAudio::UnloadMusic => self.music = None,
+                MessageToAudio::SetMusicVolume(volume) => self.music_volume = volume,
+                MessageToAudio::SetClipThreshold(threshold) => self.clip_threshold = threshold,
+                MessageToAudio::SetPlaySpeed(speed) => {
+                    self.play_speed = speed;
+                    if !self.playing {
+                        self.update_paused_play_speed();
+                    }
+                    if let Some(music) = &mut self.music {
+                        music.set_output_sample_rate(
+                            self.stream_config.sample_rate.0 as f64 / speed,
+                        );
+                    };
+                }
+                MessageToAudio::AddPlay(source) => {
+                    self.sound_effects.push(source);
+                }
+                MessageToAudio::CleanSchedules => {
+                    self.sound_effect_schedules.clear();
+                }
+                MessageToAudio::AddSchedules(mut schedules) => {
+                    schedules
+                        .sort_unstable_by(|x, y| x.timestamp.partial_cmp(&y.timestamp).unwrap());
+                    // TODO check for time rollback
+                    self.sound_effect_schedules.extend(schedules.into_iter());
+                }
+                MessageToAudio::SwitchScheduled(enabled) => {
+                    self.scheduled_play_enabled = enabled;
+                }
+                MessageToAudio::StartRecording(recorder) => self.recorder = Some(recorder),
+                MessageToAudio::StopRecording => {
+                    if let Some(recorder) = self.recorder.take() {
+                        if let Err(e) = recorder.finalize() {
+                            println!("Failed to finalize the recording");
+                            println!("Caused by: {:?}", e);
                         }
                     }
-                    _ => None,
                 }
-                .unwrap_or(0.0)
-                .clamp(-4.0, 4.0); // Prevent too large sound
+            }
+        }
+    }
 
-                self.sound_effects.retain_mut(|source| match source.next() {
-                    Some(value) => {
-                        next += value;
-                        true
-                    }
-                    None => false,
-                });
-                *out = S::from_sample(next);
+    /// Fill `output` with the music and the sound effects, recording them if requested.
+    /// While playing, the position advances by the length of `output`, even without music.
+    fn mix<S>(&mut self, output: &mut [S])
+    where
+        S: SizedSample + cpal::FromSample<f32>,
+    {
+        if self.playing {
+            self.played_sample_count += output.len().saturating_sub(self.skip_sample_count)
+                / (self.stream_config.channels as usize);
+        }
+        for out in output.iter_mut() {
+            let mut next = if !self.playing {
+                None
+            } else if self.skip_sample_count > 0 {
+                // The count-in is drained whether or not the music is loaded
+                self.skip_sample_count -= 1;
+                None
+            } else {
+                self.music
+                    .as_mut()
+                    .and_then(|music| music.next())
+                    .map(|a| a * self.music_volume)
+            }
+            .unwrap_or(0.0)
+            .clamp(-self.clip_threshold, self.clip_threshold);
+
+            self.sound_effects.retain_mut(|source| match source.next() {
+                Some(value) => {
+                    next += value;
+                    true
+                }
+                None => false,
+            });
+            *out = S::from_sample(next);
+            if let Some(recorder) = &mut self.recorder {
+                if let Err(e) = recorder.write(next) {
+                    println!("Failed to record the audio; the recording has been stopped");
+                    println!("Caused by: {:?}", e);
+                    self.recorder = None;
+                }
             }
         }
     }
 
+    /// Seek to `time`, which is clamped to the end of the music if its duration is known.  A
+    /// negative `time` is a count-in, during which silence is played until the music starts.
+    /// Without music, only the position moves.
+    fn seek(&mut self, time: f64) -> Result<(), TaikoError> {
+        let output_sample_rate = self.stream_config.sample_rate.0 as f64 / self.play_speed;
+        let (time, sample_count) = match &mut self.music {
+            Some(music) => {
+                let time = match music.duration() {
+                    Some(duration) => time.min(duration),
+                    None => time,
+                };
+                let sample_count = music.seek(time.max(0.0)).map_err(|e| TaikoError {
+                    message: e,
+                    cause: TaikoErrorCause::None,
+                })?;
+                (time, sample_count)
+            }
+            None => (time, (time.max(0.0) * output_sample_rate) as u64),
+        };
+        let skip_frame_count = (-time.min(0.0) * output_sample_rate) as usize;
+        self.skip_sample_count = skip_frame_count * (self.stream_config.channels as usize);
+        self.played_sample_count = sample_count as usize;
+        self.update_pause_state();
+        Ok(())
+    }
+
     fn update_pause_state(&self) {
         if let Some(playback_position) = self.playback_position_ptr.upgrade() {
             let mut playback_position = playback_position
@@ -581,11 +905,27 @@ impl<T> AudioThreadState<T> {
                 .map_err(|e| format!("The main thread has been panicked: {}", e))
                 .unwrap(); // Intentionally panic when error
             *playback_position = PlaybackPosition::Paused {
-                music_position: self.music_position_start(),
+                music_position: self.music_position_start() * self.play_speed,
+                play_speed: self.play_speed,
             };
         }
     }
 
+    /// While playing, the speed is updated along with the position in the stream callback.
+    fn update_paused_play_speed(&self) {
+        if let Some(playback_position) = self.playback_position_ptr.upgrade() {
+            let mut playback_position = playback_position
+                .lock()
+                .map_err(|e| format!("The main thread has been panicked: {}", e))
+                .unwrap(); // Intentionally panic when error
+            if let PlaybackPosition::Paused { play_speed, .. }
+            | PlaybackPosition::Seeking { play_speed, .. } = &mut *playback_position
+            {
+                *play_speed = self.play_speed;
+            }
+        }
+    }
+
     fn music_position_start(&self) -> f64 {
         let sample_index = self.played_sample_count as isize
             - self.skip_sample_count as isize / self.stream_config.channels as isize;
@@ -601,11 +941,93 @@ impl<T> AudioThreadState<T> {
             message: "Failed to decode music".to_string(),
             cause: TaikoErrorCause::CpalOrRodioError(CpalOrRodioError::DecoderError(e)),
         })?;
-        let ret = new_uniform_source_iterator(decoder, &self.stream_config);
-        Ok(ret)
+        let duration = decoder.total_duration().map(|d| d.as_secs_f64());
+        let source = new_uniform_source_iterator(decoder, &self.stream_config);
+        Ok(MusicSource::Streaming { source, duration })
     }
 }
 
+/// Writes the mixed output to a WAV file in the sample format of the stream.
+pub struct MixRecorder {
+    writer: WavWriter<BufWriter<File>>,
+    sample_format: SampleFormat,
+}
+
+impl MixRecorder {
+    fn create<P>(
+        path: P,
+        stream_config: &StreamConfig,
+        sample_format: SampleFormat,
+    ) -> Result<MixRecorder, TaikoError>
+    where
+        P: AsRef<Path>,
+    {
+        let (bits_per_sample, wav_sample_format) = match sample_format {
+            SampleFormat::I8 | SampleFormat::U8 => (8, hound::SampleFormat::Int),
+            SampleFormat::I16 | SampleFormat::U16 => (16, hound::SampleFormat::Int),
+            SampleFormat::I32 | SampleFormat::U32 => (32, hound::SampleFormat::Int),
+            _ => (32, hound::SampleFormat::Float),
+        };
+        let spec = WavSpec {
+            channels: stream_config.channels,
+            sample_rate: stream_config.sample_rate.0,
+            bits_per_sample,
+            sample_format: wav_sample_format,
+        };
+        let writer = WavWriter::create(path, spec).map_err(|e| TaikoError {
+            message: "Failed to create a WAV file to record the audio".to_string(),
+            cause: TaikoErrorCause::RecordError(e),
+        })?;
+        Ok(MixRecorder {
+            writer,
+            sample_format,
+        })
+    }
+
+    /// Unsigned formats are written as signed ones, since WAV has no unsigned samples except for
+    /// 8 bits, which hound converts by itself.
+    fn write(&mut self, sample: f32) -> hound::Result<()> {
+        match self.sample_format {
+            SampleFormat::I8 | SampleFormat::U8 => {
+                self.writer.write_sample(i8::from_sample(sample))
+            }
+            SampleFormat::I16 | SampleFormat::U16 => {
+                self.writer.write_sample(i16::from_sample(sample))
+            }
+            SampleFormat::I32 | SampleFormat::U32 => {
+                self.writer.write_sample(i32::from_sample(sample))
+            }
+            _ => self.writer.write_sample(sample),
+        }
+    }
+
+    fn finalize(self) -> hound::Result<()> {
+        self.writer.finalize()
+    }
+}
+
+/// Decode the whole file into interleaved samples of the given channels and sample rate.
+fn decode_file<P>(
+    filename: P,
+    channels: ChannelCount,
+    sample_rate: SampleRate,
+    description: &str,
+) -> Result<Vec<f32>, TaikoError>
+where
+    P: AsRef<Path>,
+{
+    let file = File::open(filename).map_err(|e| TaikoError {
+        message: format!("Failed to open {}", description),
+        cause: TaikoErrorCause::AudioLoadError(e),
+    })?;
+    let decoder = rodio::Decoder::new(BufReader::new(file)).map_err(|e| TaikoError {
+        message: format!("Failed to decode {}", description),
+        cause: TaikoErrorCause::CpalOrRodioError(CpalOrRodioError::DecoderError(e)),
+    })?;
+    let decoder = UniformSourceIterator::<_, f32>::new(decoder, channels, sample_rate.0);
+    Ok(decoder.collect_vec())
+}
+
 #[derive(Clone)]
 pub struct SoundBuffer {
     data: Arc<Vec<f32>>,
@@ -623,16 +1045,7 @@ impl SoundBuffer {
     where
         P: AsRef<Path>,
     {
-        let file = File::open(filename).map_err(|e| TaikoError {
-            message: "Failed to open sound chunk file".to_string(),
-            cause: TaikoErrorCause::AudioLoadError(e),
-        })?;
-        let decoder = rodio::Decoder::new(BufReader::new(file)).map_err(|e| TaikoError {
-            message: "Failed to decode sound chunk file".to_string(),
-            cause: TaikoErrorCause::CpalOrRodioError(CpalOrRodioError::DecoderError(e)),
-        })?;
-        let decoder = UniformSourceIterator::<_, f32>::new(decoder, channels, sample_rate.0);
-        let decoded = decoder.collect_vec();
+        let decoded = decode_file(filename, channels, sample_rate, "sound chunk file")?;
         Ok(SoundBuffer {
             data: Arc::new(decoded),
             channels,
@@ -645,6 +1058,7 @@ impl SoundBuffer {
             sound_buffer: self.clone(),
             wait: 0,
             index: 0,
+            pan: 0.0,
         }
     }
     pub fn set_volume(&mut self, volume: f32) {
@@ -656,6 +1070,26 @@ pub struct SoundBufferSource {
     sound_buffer: SoundBuffer,
     wait: usize,
     index: usize,
+    /// -1.0 for hard left, 0.0 for center and 1.0 for hard right
+    pan: f32,
+}
+
+impl SoundBufferSource {
+    pub fn with_pan(mut self, pan: f32) -> Self {
+        self.pan = pan.clamp(-1.0, 1.0);
+        self
+    }
+
+    /// Gain of the given output channel.  Only the first two channels (left and right) are panned,
+    /// and both of them keep the full volume at the center.
+    fn gain(&self, channel: usize) -> f32 {
+        match (self.sound_buffer.channels, channel) {
+            (1, _) => 1.0,
+            (_, 0) => (1.0 - self.pan).min(1.0),
+            (_, 1) => (1.0 + self.pan).min(1.0),
+            _ => 1.0,
+        }
+    }
 }
 
 impl Iterator for SoundBufferSource {
@@ -666,12 +1100,13 @@ impl Iterator for SoundBufferSource {
             self.wait -= 1;
             Some(0.0)
         } else {
+            let channel = self.index % self.sound_buffer.channels as usize;
             let ret = self
                 .sound_buffer
                 .data
                 .get(self.index)
                 .copied()
-                .map(|a| a * self.sound_buffer.volume);
+                .map(|a| a * self.sound_buffer.volume * self.gain(channel));
             self.index += 1;
             ret
         }
@@ -698,3 +1133,233 @@ impl Source for SoundBufferSource {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AudioThreadState, MessageToAudio, MixRecorder, MusicSource, PlaybackPosition,
+        PreloadedMusic, ResampleQuality, SoundBuffer,
+    };
+    use cpal::{BufferSize, SampleFormat, SampleRate, StreamConfig};
+    use std::path::PathBuf;
+    use std::sync::{mpsc, Arc, Mutex};
+
+    fn sound_buffer(channels: u16) -> SoundBuffer {
+        SoundBuffer {
+            data: Arc::new(vec![0.5; 8]),
+            channels,
+            sample_rate: SampleRate(44100),
+            volume: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_pan() {
+        let center = sound_buffer(2).new_source().collect::<Vec<_>>();
+        assert_eq!(center, vec![0.5; 8]);
+
+        let left = sound_buffer(2)
+            .new_source()
+            .with_pan(-1.0)
+            .collect::<Vec<_>>();
+        assert!(left.iter().step_by(2).all(|&x| x == 0.5));
+        assert!(left.iter().skip(1).step_by(2).all(|&x| x == 0.0));
+
+        let half_right = sound_buffer(2)
+            .new_source()
+            .with_pan(0.5)
+            .collect::<Vec<_>>();
+        assert_eq!(&half_right[..2], &[0.25, 0.5]);
+
+        let mono = sound_buffer(1)
+            .new_source()
+            .with_pan(-1.0)
+            .collect::<Vec<_>>();
+        assert_eq!(mono, vec![0.5; 8]);
+    }
+
+    fn preloaded_music() -> PreloadedMusic {
+        PreloadedMusic {
+            data: Arc::new(vec![0.0, 0.0, 1.0, -1.0, 2.0, -2.0, 3.0, -3.0]),
+            quality: ResampleQuality::Linear,
+            channels: 2,
+            sample_rate: 4.0,
+            output_sample_rate: 4.0,
+            position: 0.0,
+            channel: 0,
+        }
+    }
+
+    #[test]
+    fn test_preloaded_music() {
+        let mut music = preloaded_music();
+        assert_eq!(music.seek(0.5), 2);
+        assert_eq!(music.collect::<Vec<_>>(), vec![2.0, -2.0, 3.0, -3.0]);
+
+        // Twice as fast, interpolating between the frames
+        let mut music = preloaded_music();
+        music.output_sample_rate = 2.0;
+        assert_eq!(music.seek(0.25), 0);
+        assert_eq!(music.collect::<Vec<_>>(), vec![1.0, -1.0, 3.0, -3.0]);
+
+        let mut music = preloaded_music();
+        music.output_sample_rate = 8.0;
+        music.seek(0.5);
+        assert_eq!(
+            music.collect::<Vec<_>>(),
+            vec![2.0, -2.0, 2.5, -2.5, 3.0, -3.0, 3.0, -3.0]
+        );
+
+        // Cubic interpolation also reproduces a straight line, except at both ends of the data
+        let mut music = preloaded_music();
+        music.quality = ResampleQuality::Cubic;
+        music.output_sample_rate = 8.0;
+        assert_eq!(
+            music.step_by(2).collect::<Vec<_>>(),
+            vec![0.0, 0.4375, 1.0, 1.5, 2.0, 2.5625, 3.0, 3.0625]
+        );
+    }
+
+    /// A stereo state at `sample_rate`, with the sender of the messages to it and the playback
+    /// position it updates.
+    fn test_state(
+        sample_rate: u32,
+    ) -> (
+        AudioThreadState<()>,
+        mpsc::Sender<MessageToAudio<()>>,
+        Arc<Mutex<PlaybackPosition>>,
+    ) {
+        let stream_config = StreamConfig {
+            channels: 2,
+            sample_rate: SampleRate(sample_rate),
+            buffer_size: BufferSize::Default,
+        };
+        let (sender_to_audio, receiver_to_audio) = mpsc::channel();
+        let (sound_effect_sender, _) = mpsc::channel();
+        let playback_position = Arc::new(Mutex::new(PlaybackPosition::NotStarted));
+        let state = AudioThreadState::new(
+            stream_config,
+            receiver_to_audio,
+            sound_effect_sender,
+            Arc::downgrade(&playback_position),
+        );
+        (state, sender_to_audio, playback_position)
+    }
+
+    #[test]
+    fn test_record_mix() {
+        let (mut state, sender_to_audio, _) = test_state(44100);
+
+        let path = std::env::temp_dir().join("taiko-untitled-test-record-mix.wav");
+        let recorder = MixRecorder::create(&path, &state.stream_config, SampleFormat::I16).unwrap();
+        sender_to_audio
+            .send(MessageToAudio::StartRecording(recorder))
+            .unwrap();
+        sender_to_audio
+            .send(MessageToAudio::AddPlay(sound_buffer(2).new_source()))
+            .unwrap();
+        let mut output = [0.0f32; 4];
+        for _ in 0..3 {
+            state.receive_messages();
+            state.mix(&mut output);
+        }
+        sender_to_audio.send(MessageToAudio::StopRecording).unwrap();
+        state.receive_messages();
+
+        let samples = hound::WavReader::open(&path)
+            .unwrap()
+            .into_samples::<i16>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(samples.len(), 12);
+        assert!(samples[..8].iter().all(|&x| x == i16::MAX / 2 + 1));
+        assert!(samples[8..].iter().all(|&x| x == 0));
+    }
+
+    #[test]
+    fn test_load_missing_music() {
+        let (mut state, sender_to_audio, playback_position) = test_state(4);
+        state.music = Some(MusicSource::Preloaded(preloaded_music()));
+
+        let (result_sender, result_receiver) = mpsc::channel();
+        sender_to_audio
+            .send(MessageToAudio::LoadMusic(
+                PathBuf::from("no_such_music.ogg"),
+                result_sender,
+            ))
+            .unwrap();
+        state.receive_messages();
+        assert!(result_receiver.recv().unwrap().is_err());
+        assert!(state.music.is_none());
+
+        // The play goes on in silence
+        state.playing = true;
+        state.seek(0.5).unwrap();
+        assert_eq!(
+            playback_position.lock().unwrap().music_position(),
+            Some(0.5)
+        );
+        let mut output = [1.0f32; 8];
+        state.mix(&mut output);
+        assert_eq!(output, [0.0; 8]);
+        assert_eq!(state.music_position_start(), 1.5);
+
+        // The count-in passes as well
+        state.seek(-0.5).unwrap();
+        assert_eq!(state.music_position_start(), -0.5);
+        state.mix(&mut output);
+        assert_eq!(state.music_position_start(), 0.5);
+        state.mix(&mut output);
+        assert_eq!(state.music_position_start(), 1.5);
+    }
+
+    #[test]
+    fn test_seek() {
+        let (mut state, sender_to_audio, playback_position) = test_state(4);
+        state.music = Some(MusicSource::Preloaded(preloaded_music()));
+        state.playing = true;
+        let music_position = || playback_position.lock().unwrap().music_position();
+
+        state.seek(-0.5).unwrap();
+        assert_eq!(music_position(), Some(-0.5));
+        let mut output = [0.0f32; 8];
+        state.mix(&mut output);
+        assert_eq!(output, [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, -1.0]);
+
+        state.seek(0.0).unwrap();
+        assert_eq!(music_position(), Some(0.0));
+
+        state.seek(2.0).unwrap();
+        assert_eq!(music_position(), Some(1.0));
+        state.mix(&mut output);
+        assert_eq!(output, [0.0; 8]);
+
+        sender_to_audio
+            .send(MessageToAudio::SetPlaySpeed(2.0))
+            .unwrap();
+        state.receive_messages();
+        state.seek(-1.0).unwrap();
+        assert_eq!(music_position(), Some(-1.0));
+        state.seek(0.5).unwrap();
+        assert_eq!(music_position(), Some(0.5));
+    }
+
+    #[test]
+    fn test_playback_state() {
+        let (mut state, sender_to_audio, playback_position) = test_state(4);
+        state.music = Some(MusicSource::Preloaded(preloaded_music()));
+        let playback_state = || playback_position.lock().unwrap().playback_state();
+        assert_eq!(playback_state(), None);
+
+        state.seek(0.5).unwrap();
+        assert_eq!(playback_state(), Some((0.5, 1.0, false)));
+
+        // The speed is reflected even while paused
+        sender_to_audio
+            .send(MessageToAudio::SetPlaySpeed(2.0))
+            .unwrap();
+        state.receive_messages();
+        assert_eq!(playback_state(), Some((0.5, 2.0, false)));
+    }
+}
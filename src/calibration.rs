@@ -0,0 +1,188 @@
+use crate::assets::Assets;
+use crate::audio::{AudioManager, SoundEffectSchedule};
+use crate::config::TaikoConfig;
+use crate::errors::TaikoError;
+use crate::game::{event_music_position, AutoEvent};
+use crate::game_graphics::{draw_calibration, handle_window_event};
+use crate::structs::{NoteColor, NoteSize, SingleNoteKind};
+use crate::utils::FrameLimiter;
+use itertools::Itertools;
+use ordered_float::OrderedFloat;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::render::WindowCanvas;
+use sdl2::{EventPump, TimerSubsystem};
+
+/// Interval of the metronome in seconds, i.e. 120 BPM.
+const BEAT_DURATION: f64 = 0.5;
+/// Beats to get used to the tempo, whose taps are not measured.
+const LEAD_IN_BEATS: usize = 4;
+/// Beats whose taps are measured.
+const MEASURED_BEATS: usize = 16;
+
+pub enum CalibrationBreak {
+    /// Contains the suggested input offset, or `None` if nothing was measured.
+    Done(Option<f64>),
+    Exit,
+}
+
+/// Play a metronome and measure how far the taps are off from it.
+/// The music is muted during calibration, and playback is paused afterwards.
+pub fn calibrate(
+    config: &TaikoConfig,
+    canvas: &mut WindowCanvas,
+    event_pump: &mut EventPump,
+    timer_subsystem: &mut TimerSubsystem,
+    audio_manager: &AudioManager<AutoEvent>,
+    assets: &Assets,
+) -> Result<CalibrationBreak, TaikoError> {
+    audio_manager.set_music_volume(0.0)?;
+    let res = calibration_loop(
+        config,
+        canvas,
+        event_pump,
+        timer_subsystem,
+        audio_manager,
+        assets,
+    );
+    audio_manager.pause()?;
+    audio_manager.clear_play_schedules()?;
+    audio_manager.set_music_volume(config.volume.song / 100.0)?;
+    res
+}
+
+fn calibration_loop(
+    config: &TaikoConfig,
+    canvas: &mut WindowCanvas,
+    event_pump: &mut EventPump,
+    timer_subsystem: &mut TimerSubsystem,
+    audio_manager: &AudioManager<AutoEvent>,
+    assets: &Assets,
+) -> Result<CalibrationBreak, TaikoError> {
+    let start = audio_manager.music_position()?.unwrap_or(0.0) + 1.0;
+    let beats = (0..LEAD_IN_BEATS + MEASURED_BEATS)
+        .map(|i| start + i as f64 * BEAT_DURATION)
+        .collect_vec();
+    let schedules = beats
+        .iter()
+        .enumerate()
+        .map(|(i, &time)| {
+            // Lead-in beats sound different so that the user knows when to start
+            let (color, chunk) = if i < LEAD_IN_BEATS {
+                (NoteColor::Ka, &assets.chunks.sound_ka)
+            } else {
+                (NoteColor::Don, &assets.chunks.sound_don)
+            };
+            SoundEffectSchedule {
+                timestamp: time,
+                source: chunk.new_source(),
+                volume: 1.0,
+                pan: 0.0,
+                response: AutoEvent {
+                    time,
+                    kind: SingleNoteKind {
+                        color,
+                        size: NoteSize::Small,
+                    },
+                },
+            }
+        })
+        .collect();
+    audio_manager.clear_play_schedules()?;
+    audio_manager.add_play_schedules(schedules)?;
+    audio_manager.set_play_scheduled(true)?;
+    audio_manager.play()?;
+
+    let measured_beats = &beats[LEAD_IN_BEATS..];
+    let mut taps = Vec::new();
+    let mut frame_limiter = FrameLimiter::new(config.window.fps_cap());
+    loop {
+        let music_position = audio_manager.music_position()?;
+        let sdl_timestamp = timer_subsystem.ticks();
+
+        let mut timestamps = Vec::new();
+        for event in event_pump.poll_iter() {
+            handle_window_event(canvas, &event)?;
+            match event {
+                Event::Quit { .. } => return Ok(CalibrationBreak::Exit),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Q),
+                    ..
+                } => return Ok(CalibrationBreak::Done(None)),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat: false,
+                    timestamp,
+                    ..
+                } if config.key.color(keycode).is_some() => timestamps.push(timestamp),
+                Event::JoyButtonDown {
+                    button_idx,
+                    timestamp,
+                    ..
+                } if config.joystick.color(button_idx).is_some() => timestamps.push(timestamp),
+                _ => {}
+            }
+        }
+        audio_manager.sound_effect_receiver.try_iter().count(); // Consume all
+
+        if let Some(music_position) = music_position {
+            taps.extend(
+                timestamps.into_iter().map(|timestamp| {
+                    event_music_position(music_position, timestamp, sdl_timestamp)
+                }),
+            );
+            if music_position > start + beats.len() as f64 * BEAT_DURATION {
+                return Ok(CalibrationBreak::Done(suggest_input_offset(
+                    measured_beats,
+                    &taps,
+                )));
+            }
+        }
+
+        let seconds_after_beat = music_position.and_then(|music_position| {
+            beats
+                .iter()
+                .rev()
+                .find(|&&time| time <= music_position)
+                .map(|time| music_position - time)
+        });
+        let tap_count = measured_errors(measured_beats, &taps).count();
+        draw_calibration(canvas, assets, seconds_after_beat, tap_count)?;
+
+        canvas.present();
+        frame_limiter.wait();
+    }
+}
+
+/// Signed errors of the taps against the nearest beats; positive if the tap is late.
+/// Taps too far from any beat are ignored.
+fn measured_errors<'a>(beats: &'a [f64], taps: &'a [f64]) -> impl Iterator<Item = f64> + 'a {
+    taps.iter().filter_map(move |&tap| {
+        beats
+            .iter()
+            .map(|&beat| tap - beat)
+            .min_by_key(|&error| OrderedFloat::from(error.abs()))
+            .filter(|error| error.abs() < BEAT_DURATION / 2.0)
+    })
+}
+
+/// Returns the mean error of the taps, which is the suggested input offset.
+fn suggest_input_offset(beats: &[f64], taps: &[f64]) -> Option<f64> {
+    let (sum, count) = measured_errors(beats, taps).fold((0.0, 0), |(s, c), e| (s + e, c + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::suggest_input_offset;
+
+    #[test]
+    fn test_suggest_input_offset() {
+        let beats = [1.0, 1.5, 2.0, 2.5];
+        assert_eq!(suggest_input_offset(&beats, &[]), None);
+        // The last tap is too far from any beat
+        let taps = [1.02, 1.53, 2.01, 2.54, 3.5];
+        let offset = suggest_input_offset(&beats, &taps).unwrap();
+        assert!((offset - 0.025).abs() < 1e-9);
+    }
+}
@@ -1,10 +1,25 @@
+use crate::audio::ResampleQuality;
+use crate::structs::{DrumSide, NoteColor};
 use config::{Config, ConfigError};
+use sdl2::keyboard::Keycode;
 use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+use toml_edit::{table, value, DocumentMut, Item};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TaikoConfig {
+    /// Directory containing `img` and `snd`
+    pub assets_dir: PathBuf,
     pub window: WindowConfig,
     pub volume: VolumeConfig,
+    pub audio: AudioConfig,
+    pub display: DisplayConfig,
+    pub combo: ComboConfig,
+    pub key: KeyBindings,
+    pub joystick: ButtonBindings,
+    pub replay: ReplayConfig,
+    pub judge: JudgeConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -12,7 +27,24 @@ pub struct WindowConfig {
     pub width: u32,
     pub height: u32,
     pub vsync: bool,
-    pub fps: f64,
+    /// Upper bound of the frame rate, which also applies with vsync.  If not set, it is 60
+    /// without vsync and unlimited with it.  Zero makes it unlimited.
+    pub fps: Option<f64>,
+}
+
+/// The frame rate cap used when `window.fps` is not set and vsync is off
+const DEFAULT_FPS: f64 = 60.0;
+
+impl WindowConfig {
+    /// Returns the upper bound of the frame rate, or `None` if it is unlimited.
+    pub fn fps_cap(&self) -> Option<f64> {
+        match self.fps {
+            Some(fps) if fps == 0.0 => None,
+            Some(fps) => Some(fps),
+            None if self.vsync => None,
+            None => Some(DEFAULT_FPS),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -21,27 +53,501 @@ pub struct VolumeConfig {
     pub se: f32,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AudioConfig {
+    /// Decode the whole music before playing it, which takes longer to load but avoids glitches
+    /// at the start of large files
+    pub preload_music: bool,
+    /// Resampling of the preloaded music at a changed speed, either `"linear"` or `"cubic"`.
+    /// The streaming music is resampled by its decoder, so `"cubic"` requires `preload_music`.
+    pub resample_quality: ResampleQuality,
+    /// The music is clamped to this amplitude before the sound effects are added
+    pub clip_threshold: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DisplayConfig {
+    /// Use `*_high_contrast.png` for the images of notes and the drum, whose don and ka are
+    /// easier to tell apart
+    pub high_contrast_notes: bool,
+    /// Grow the notes as they enter the playfield and shrink them as they leave it, instead of
+    /// letting them pop in and out at its edges
+    pub scale_notes_at_edges: bool,
+}
+
+/// The least combo shown in each color of the combo number.  The combo is shown in the
+/// highest color it has reached, and is hidden below all of them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ComboConfig {
+    pub white: i64,
+    pub silver: i64,
+    pub gold: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComboColor {
+    White,
+    Silver,
+    Gold,
+}
+
+impl ComboConfig {
+    pub fn color(&self, combo: i64) -> Option<ComboColor> {
+        match combo {
+            _ if combo >= self.gold => Some(ComboColor::Gold),
+            _ if combo >= self.silver => Some(ComboColor::Silver),
+            _ if combo >= self.white => Some(ComboColor::White),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JudgeConfig {
+    /// Seconds subtracted from the time of every hit; positive if the player tends to hit late
+    pub input_offset: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReplayConfig {
+    /// Save the hits of each play to `file`
+    pub record: bool,
+    /// Play with the hits loaded from `file` instead of the live input
+    pub play: bool,
+    pub file: String,
+}
+
+/// Keys for each side of the drum, specified by SDL key names such as `"X"` or `"/"`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyBindings {
+    #[serde(with = "keycodes")]
+    pub don_left: Vec<Keycode>,
+    #[serde(with = "keycodes")]
+    pub don_right: Vec<Keycode>,
+    #[serde(with = "keycodes")]
+    pub ka_left: Vec<Keycode>,
+    #[serde(with = "keycodes")]
+    pub ka_right: Vec<Keycode>,
+}
+
+impl KeyBindings {
+    /// Returns the color of the note that the key hits, if bound.
+    pub fn color(&self, keycode: Keycode) -> Option<NoteColor> {
+        if self.don_left.contains(&keycode) || self.don_right.contains(&keycode) {
+            Some(NoteColor::Don)
+        } else if self.ka_left.contains(&keycode) || self.ka_right.contains(&keycode) {
+            Some(NoteColor::Ka)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the side of the drum that the key hits, if bound.
+    pub fn side(&self, keycode: Keycode) -> Option<DrumSide> {
+        if self.don_left.contains(&keycode) || self.ka_left.contains(&keycode) {
+            Some(DrumSide::Left)
+        } else if self.don_right.contains(&keycode) || self.ka_right.contains(&keycode) {
+            Some(DrumSide::Right)
+        } else {
+            None
+        }
+    }
+}
+
+/// Joystick buttons for each side of the drum, specified by SDL button indices.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ButtonBindings {
+    pub don_left: Vec<u8>,
+    pub don_right: Vec<u8>,
+    pub ka_left: Vec<u8>,
+    pub ka_right: Vec<u8>,
+}
+
+impl ButtonBindings {
+    /// Returns the color of the note that the button hits, if bound.
+    pub fn color(&self, button_idx: u8) -> Option<NoteColor> {
+        if self.don_left.contains(&button_idx) || self.don_right.contains(&button_idx) {
+            Some(NoteColor::Don)
+        } else if self.ka_left.contains(&button_idx) || self.ka_right.contains(&button_idx) {
+            Some(NoteColor::Ka)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the side of the drum that the button hits, if bound.
+    pub fn side(&self, button_idx: u8) -> Option<DrumSide> {
+        if self.don_left.contains(&button_idx) || self.ka_left.contains(&button_idx) {
+            Some(DrumSide::Left)
+        } else if self.don_right.contains(&button_idx) || self.ka_right.contains(&button_idx) {
+            Some(DrumSide::Right)
+        } else {
+            None
+        }
+    }
+}
+
+mod keycodes {
+    use sdl2::keyboard::Keycode;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        keycodes: &[Keycode],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(keycodes.iter().map(|keycode| keycode.name()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Keycode>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|name| {
+                Keycode::from_name(name)
+                    .ok_or_else(|| D::Error::custom(format!("Unknown key name: {:?}", name)))
+            })
+            .collect()
+    }
+}
+
 impl Default for TaikoConfig {
     fn default() -> Self {
         TaikoConfig {
+            assets_dir: PathBuf::from("assets"),
             window: WindowConfig {
                 width: 1920,
                 height: 1080,
                 vsync: false,
-                fps: 60.0,
+                fps: None,
             },
             volume: VolumeConfig {
                 song: 100.0,
                 se: 100.0,
             },
+            audio: AudioConfig {
+                preload_music: false,
+                resample_quality: ResampleQuality::Linear,
+                clip_threshold: 4.0,
+            },
+            display: DisplayConfig {
+                high_contrast_notes: false,
+                scale_notes_at_edges: false,
+            },
+            combo: ComboConfig {
+                white: 10,
+                silver: 50,
+                gold: 100,
+            },
+            key: KeyBindings {
+                don_left: vec![Keycode::X, Keycode::S],
+                don_right: vec![Keycode::Slash, Keycode::Colon],
+                ka_left: vec![Keycode::Z, Keycode::A],
+                ka_right: vec![
+                    Keycode::Underscore,
+                    Keycode::Backslash,
+                    Keycode::RightBracket,
+                ],
+            },
+            joystick: ButtonBindings {
+                don_left: vec![6],
+                don_right: vec![7],
+                ka_left: vec![4],
+                ka_right: vec![5],
+            },
+            replay: ReplayConfig {
+                record: false,
+                play: false,
+                file: "replay.json".to_owned(),
+            },
+            judge: JudgeConfig { input_offset: 0.0 },
+        }
+    }
+}
+
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Written on the first run.  Every entry is commented out, so the defaults above are used
+/// until the user uncomments one.
+const DEFAULT_CONFIG_FILE: &str = r#"# Configuration of taiko-untitled.  Uncomment an entry to change it.
+
+# assets_dir = "assets"
+
+[window]
+# width = 1920
+# height = 1080
+# vsync = false
+# Upper bound of the frame rate, which is 60 without vsync and unlimited with it unless set.
+# Set it to 0 to make it unlimited.
+# fps = 60.0
+
+[volume]
+# song = 100.0
+# se = 100.0
+
+[audio]
+# preload_music = false
+# Resampling of the music played at a changed speed, either "linear" or "cubic".
+# Only the preloaded music can be resampled with "cubic".
+# resample_quality = "linear"
+# clip_threshold = 4.0
+
+[display]
+# high_contrast_notes = false
+# scale_notes_at_edges = false
+
+[combo]
+# white = 10
+# silver = 50
+# gold = 100
+
+[key]
+# don_left = ["X", "S"]
+# don_right = ["/", ":"]
+# ka_left = ["Z", "A"]
+# ka_right = ["_", "\\", "]"]
+
+[joystick]
+# don_left = [6]
+# don_right = [7]
+# ka_left = [4]
+# ka_right = [5]
+
+[replay]
+# record = false
+# play = false
+# file = "replay.json"
+
+[judge]
+# input_offset = 0.0
+"#;
+
+/// Write the default config file if it does not exist yet.  Failure is not fatal.
+fn create_default_config_file() {
+    let result = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(CONFIG_FILE_NAME)
+        .and_then(|mut file| file.write_all(DEFAULT_CONFIG_FILE.as_bytes()));
+    match result {
+        Ok(()) => println!("Created default {}", CONFIG_FILE_NAME),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => {
+            println!("Failed to create default {}", CONFIG_FILE_NAME);
+            println!("Caused by: {:?}", e);
         }
     }
 }
 
 pub fn get_config() -> Result<TaikoConfig, ConfigError> {
+    create_default_config_file();
     Config::builder()
         .add_source(Config::try_from(&TaikoConfig::default())?)
-        .add_source(config::File::with_name("config.toml").required(false))
+        .add_source(config::File::with_name(CONFIG_FILE_NAME).required(false))
         .build()?
         .try_deserialize::<TaikoConfig>()
+        .and_then(TaikoConfig::validate)
+}
+
+impl TaikoConfig {
+    /// Check the values that deserialize fine but cannot be used.
+    fn validate(self) -> Result<Self, ConfigError> {
+        let invalid = |message: &str| Err(ConfigError::Message(message.to_owned()));
+        if self.window.width == 0 || self.window.height == 0 {
+            return invalid("window.width and window.height must be positive");
+        }
+        if matches!(self.window.fps, Some(fps) if fps.is_nan() || fps < 0.0) {
+            return invalid("window.fps must not be negative");
+        }
+        if !(0.0..=100.0).contains(&self.volume.song) || !(0.0..=100.0).contains(&self.volume.se) {
+            return invalid("volume.song and volume.se must be between 0 and 100");
+        }
+        if self.audio.clip_threshold.is_nan() || self.audio.clip_threshold <= 0.0 {
+            return invalid("audio.clip_threshold must be positive");
+        }
+        if self.audio.resample_quality != ResampleQuality::Linear && !self.audio.preload_music {
+            return invalid(
+                "audio.resample_quality other than \"linear\" needs audio.preload_music",
+            );
+        }
+        Ok(self)
+    }
+
+    /// Take the settings of `new_config` that can be changed while the game is running.
+    /// `window.vsync`, `assets_dir` and `display` are kept, since the window and the assets are
+    /// not re-created; the new values take effect after a restart.
+    pub fn update_runtime_settings(&mut self, new_config: TaikoConfig) {
+        if new_config.window.vsync != self.window.vsync
+            || new_config.assets_dir != self.assets_dir
+            || new_config.display.high_contrast_notes != self.display.high_contrast_notes
+        {
+            println!(
+                "window.vsync, assets_dir and display.high_contrast_notes will take effect after restart"
+            );
+        }
+        self.window.width = new_config.window.width;
+        self.window.height = new_config.window.height;
+        self.window.fps = new_config.window.fps;
+        self.volume = new_config.volume;
+        self.audio = new_config.audio;
+        self.display.scale_notes_at_edges = new_config.display.scale_notes_at_edges;
+        self.combo = new_config.combo;
+        self.key = new_config.key;
+        self.joystick = new_config.joystick;
+        self.replay = new_config.replay;
+        self.judge = new_config.judge;
+    }
+}
+
+/// Write `judge.input_offset` to the config file, leaving the other entries and comments intact.
+pub fn save_input_offset(input_offset: f64) -> Result<(), ConfigError> {
+    let content = match std::fs::read_to_string(CONFIG_FILE_NAME) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(ConfigError::Foreign(Box::new(e))),
+    };
+    let content = set_input_offset(&content, input_offset)?;
+    std::fs::write(CONFIG_FILE_NAME, content).map_err(|e| ConfigError::Foreign(Box::new(e)))
+}
+
+fn set_input_offset(content: &str, input_offset: f64) -> Result<String, ConfigError> {
+    let mut document = content
+        .parse::<DocumentMut>()
+        .map_err(|e| ConfigError::Foreign(Box::new(e)))?;
+    let judge = document
+        .entry("judge")
+        .or_insert(table())
+        .as_table_like_mut()
+        .ok_or_else(|| ConfigError::Message("`judge` in the config file is not a table".into()))?;
+    // Replacing only the value keeps the comments above the key
+    *judge.entry("input_offset").or_insert(Item::None) = value(input_offset);
+    Ok(document.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{set_input_offset, ComboColor, TaikoConfig, WindowConfig, DEFAULT_CONFIG_FILE};
+    use crate::audio::ResampleQuality;
+    use config::Config;
+    use sdl2::keyboard::Keycode;
+    use std::path::PathBuf;
+    use toml_edit::DocumentMut;
+
+    #[test]
+    fn test_default_config_file_is_valid_toml() {
+        let uncommented = DEFAULT_CONFIG_FILE
+            .lines()
+            .map(|line| match line.strip_prefix("# ") {
+                Some(entry) if entry.contains(" = ") => entry,
+                _ => line,
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let document = uncommented.parse::<DocumentMut>().unwrap();
+        assert_eq!(document["assets_dir"].as_str(), Some("assets"));
+        assert_eq!(document["window"]["width"].as_integer(), Some(1920));
+        assert_eq!(document["key"]["ka_right"][1].as_str(), Some("\\"));
+        assert_eq!(document["judge"]["input_offset"].as_float(), Some(0.0));
+    }
+
+    #[test]
+    fn test_set_input_offset() {
+        let content = "# Judge\n[judge]\n# Seconds\ninput_offset = 0.0\n";
+        assert_eq!(
+            set_input_offset(content, 0.5).unwrap(),
+            "# Judge\n[judge]\n# Seconds\ninput_offset = 0.5\n"
+        );
+        let document = set_input_offset("", -0.25)
+            .unwrap()
+            .parse::<DocumentMut>()
+            .unwrap();
+        assert_eq!(document["judge"]["input_offset"].as_float(), Some(-0.25));
+        let document = set_input_offset("judge = { lenient = true }", 0.5)
+            .unwrap()
+            .parse::<DocumentMut>()
+            .unwrap();
+        assert_eq!(document["judge"]["input_offset"].as_float(), Some(0.5));
+        assert_eq!(document["judge"]["lenient"].as_bool(), Some(true));
+
+        assert!(set_input_offset("judge = 1", 0.5).is_err());
+    }
+
+    #[test]
+    fn test_fps_cap() {
+        let load = |file: &str| {
+            Config::builder()
+                .add_source(Config::try_from(&TaikoConfig::default().window).unwrap())
+                .add_source(config::File::from_str(file, config::FileFormat::Toml))
+                .build()
+                .unwrap()
+                .try_deserialize::<WindowConfig>()
+                .unwrap()
+        };
+        assert_eq!(load("").fps_cap(), Some(60.0));
+        assert_eq!(load("vsync = true").fps_cap(), None);
+        assert_eq!(load("fps = 144.0").fps_cap(), Some(144.0));
+        assert_eq!(load("vsync = true\nfps = 144.0").fps_cap(), Some(144.0));
+        assert_eq!(load("fps = 0.0").fps_cap(), None);
+    }
+
+    #[test]
+    fn test_validate_clip_threshold() {
+        let with_threshold = |clip_threshold| {
+            let mut config = TaikoConfig::default();
+            config.audio.clip_threshold = clip_threshold;
+            config.validate().is_ok()
+        };
+        assert!(with_threshold(4.0));
+        assert!(with_threshold(0.5));
+        assert!(!with_threshold(0.0));
+        assert!(!with_threshold(-1.0));
+        assert!(!with_threshold(f32::NAN));
+    }
+
+    #[test]
+    fn test_validate_resample_quality() {
+        let with_quality = |resample_quality, preload_music| {
+            let mut config = TaikoConfig::default();
+            config.audio.resample_quality = resample_quality;
+            config.audio.preload_music = preload_music;
+            config.validate().is_ok()
+        };
+        assert!(with_quality(ResampleQuality::Linear, false));
+        assert!(with_quality(ResampleQuality::Linear, true));
+        assert!(with_quality(ResampleQuality::Cubic, true));
+        assert!(!with_quality(ResampleQuality::Cubic, false));
+    }
+
+    #[test]
+    fn test_combo_color() {
+        let combo = TaikoConfig::default().combo;
+        assert_eq!(combo.color(0), None);
+        assert_eq!(combo.color(9), None);
+        assert_eq!(combo.color(10), Some(ComboColor::White));
+        assert_eq!(combo.color(49), Some(ComboColor::White));
+        assert_eq!(combo.color(50), Some(ComboColor::Silver));
+        assert_eq!(combo.color(99), Some(ComboColor::Silver));
+        assert_eq!(combo.color(100), Some(ComboColor::Gold));
+        assert_eq!(combo.color(1000), Some(ComboColor::Gold));
+    }
+
+    #[test]
+    fn test_update_runtime_settings() {
+        let mut config = TaikoConfig::default();
+        let mut new_config = TaikoConfig {
+            assets_dir: PathBuf::from("other_assets"),
+            ..Default::default()
+        };
+        new_config.window.vsync = true;
+        new_config.window.fps = Some(144.0);
+        new_config.key.don_left = vec![Keycode::F];
+        new_config.judge.input_offset = 0.02;
+        config.update_runtime_settings(new_config);
+        assert_eq!(config.assets_dir, PathBuf::from("assets"));
+        assert!(!config.window.vsync);
+        assert_eq!(config.window.fps, Some(144.0));
+        assert_eq!(config.key.don_left, vec![Keycode::F]);
+        assert_eq!(config.judge.input_offset, 0.02);
+    }
 }
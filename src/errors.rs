@@ -24,6 +24,8 @@ pub enum TaikoErrorCause {
     CpalOrRodioError(CpalOrRodioError),
     InvalidResourceError,
     TjaLoadError(TjaError),
+    ReplayError(ReplayError),
+    RecordError(hound::Error),
 }
 
 #[derive(Debug, From)]
@@ -37,6 +39,12 @@ pub enum CpalOrRodioError {
     DecoderError(DecoderError),
 }
 
+#[derive(Debug, From)]
+pub enum ReplayError {
+    IoError(io::Error),
+    JsonError(serde_json::Error),
+}
+
 pub fn new_sdl_error<S>(message: S, sdl_message: String) -> TaikoError
 where
     S: ToString,
@@ -97,6 +105,17 @@ where
     }
 }
 
+pub fn new_replay_error<S, E>(message: S, replay_error: E) -> TaikoError
+where
+    S: ToString,
+    E: Into<ReplayError>,
+{
+    TaikoError {
+        message: message.to_string(),
+        cause: TaikoErrorCause::ReplayError(replay_error.into()),
+    }
+}
+
 pub fn no_score_in_tja() -> TaikoError {
     TaikoError {
         message: "There is no score in the tja file".to_owned(),
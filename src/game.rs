@@ -1,63 +1,141 @@
 use crate::assets::Assets;
 use crate::audio::SoundBuffer;
-use crate::audio::{AudioManager, SoundEffectSchedule};
-use crate::config::TaikoConfig;
+use crate::audio::{AudioManager, PreloadedMusic, SoundEffectSchedule};
+use crate::calibration::{calibrate, CalibrationBreak};
+use crate::config::{
+    get_config, save_input_offset, ButtonBindings, ComboColor, KeyBindings, TaikoConfig,
+    CONFIG_FILE_NAME,
+};
 use crate::errors::no_score_in_tja;
-use crate::errors::{new_sdl_error, new_tja_error, to_sdl_error, TaikoError};
-use crate::game_graphics::game_rect;
+use crate::errors::{new_sdl_error, new_tja_error, to_sdl_error, TaikoError, TaikoErrorCause};
 use crate::game_graphics::{
-    draw_background, draw_bar_lines, draw_branch_overlay, draw_combo, draw_flying_notes,
-    draw_gauge, draw_judge_strs, draw_notes,
+    draw_background, draw_balloon_bursts, draw_bar_lines, draw_branch_overlay, draw_combo,
+    draw_drum_hits, draw_flying_notes, draw_fps, draw_gauge, draw_gogo_overlay, draw_judge_counts,
+    draw_judge_strs, draw_measure_number, draw_notes, draw_renda_badge, draw_score,
+    handle_window_event, visible_notes,
 };
-use crate::game_manager::{GameManager, OfGameState};
+use crate::game_graphics::{fit_playfield_to_window, game_rect};
+use crate::game_manager::{DrumHit, GameManager, GameResult, OfGameState};
 use crate::mode::GameMode;
 use crate::pause::pause;
 use crate::pause::PauseBreak;
+use crate::replay::{Replay, ReplayPlayer};
 use crate::structs::SingleNoteKind;
 use crate::structs::{
-    just,
     just::Score,
     typed::{Branch, NoteContent, RendaContent, RendaKind, Score as TypedScore},
-    BarLine, BranchType, NoteColor, NoteSize,
+    BarLine, Bpm, BranchType, DrumSide, NoteColor, NoteSize,
 };
-use crate::tja::load_tja_from_file;
-use crate::utils::to_digits;
+use crate::tja::{load_tja_from_file, Song};
+use crate::utils::{to_digits, FrameLimiter};
 use itertools::{iterate, Itertools};
 use notify::RecursiveMode;
 use notify::Watcher;
 use num::clamp;
 use sdl2::event::{Event, EventWatch, EventWatchCallback};
+use sdl2::joystick::Joystick;
 use sdl2::keyboard::{Keycode, Mod};
 use sdl2::render::WindowCanvas;
-use sdl2::{EventPump, EventSubsystem, TimerSubsystem};
+use sdl2::{EventPump, EventSubsystem, JoystickSubsystem, TimerSubsystem};
 use std::convert::TryInto;
 use std::iter::Peekable;
 use std::path::Path;
 use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
 use std::time::Duration;
 
 type ScoreOfGameState = TypedScore<OfGameState>;
 
+/// Seconds to wait after the last note before showing the result.
+const FINISH_DELAY: f64 = 2.0;
+
 enum GameBreak {
     Pause(f64),
+    Finish(GameResult),
     Escape,
     Exit,
 }
 
+/// What `play` should do after a frame processed by `game_loop`
+enum FrameResult {
+    Continue,
+    /// Play again from the given time, i.e. the start of the loop section
+    Loop(f64),
+    Break(GameBreak),
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct GameUserState {
     pub time: f64,
     pub auto: bool,
     pub speed: f64,
+    pub show_measure: bool,
+    pub show_fps: bool,
+    pub show_judge_counts: bool,
+    /// Play a short snippet of the music whenever the position is moved in the pause screen
+    pub scrub_preview: bool,
+    pub loop_start: Option<f64>,
+    pub loop_end: Option<f64>,
+    pub input_offset: f64,
+}
+
+impl GameUserState {
+    /// Returns the section to repeat, if both markers are set in the right order.
+    pub fn loop_section(&self) -> Option<(f64, f64)> {
+        match (self.loop_start, self.loop_end) {
+            (Some(start), Some(end)) if start < end => Some((start, end)),
+            _ => None,
+        }
+    }
+
+    /// Returns the time used for judging at `music_position`.  The input offset is not applied
+    /// in the auto play, whose hits are sent at the exact time of the notes.
+    pub fn judge_position(&self, music_position: f64) -> f64 {
+        if self.auto {
+            music_position
+        } else {
+            music_position - self.input_offset
+        }
+    }
+}
+
+/// Weight of the latest frame in the smoothed frame time.
+const FPS_SMOOTHING: f64 = 0.1;
+
+/// Frame rate measured from the SDL ticks of consecutive frames, smoothed exponentially.
+#[derive(Default)]
+struct FpsCounter {
+    last_ticks: Option<u32>,
+    frame_time: Option<f64>,
+}
+
+impl FpsCounter {
+    fn update(&mut self, ticks: u32) {
+        if let Some(last_ticks) = self.last_ticks {
+            let delta = ticks.wrapping_sub(last_ticks) as f64 / 1000.0;
+            self.frame_time = Some(match self.frame_time {
+                Some(frame_time) => frame_time + (delta - frame_time) * FPS_SMOOTHING,
+                None => delta,
+            });
+        }
+        self.last_ticks = Some(ticks);
+    }
+
+    fn fps(&self) -> Option<f64> {
+        self.frame_time
+            .filter(|&frame_time| frame_time > 0.0)
+            .map(|frame_time| 1.0 / frame_time)
+    }
 }
 
 #[allow(clippy::too_many_arguments)]
 pub fn game<P>(
-    config: &TaikoConfig,
+    config: &mut TaikoConfig,
     canvas: &mut WindowCanvas,
     event_subsystem: &EventSubsystem,
     event_pump: &mut EventPump,
     timer_subsystem: &mut TimerSubsystem,
+    joystick_subsystem: &JoystickSubsystem,
     audio_manager: &AudioManager<AutoEvent>,
     assets: &mut Assets,
     tja_file_name: P,
@@ -68,37 +146,60 @@ where
     let mut song = load_tja_from_file(&tja_file_name)
         .map_err(|e| new_tja_error("Failed to load tja file", e))?;
 
-    if let Some(song_wave_path) = &song.wave {
-        audio_manager.load_music(song_wave_path)?;
+    assets.set_song_background(song.bg_image.as_deref());
+    show_song_title(canvas, &song)?;
+    let music_result = match &song.wave {
+        Some(song_wave_path) if config.audio.preload_music => {
+            let music_receiver =
+                audio_manager.preload_music(song_wave_path, config.audio.resample_quality);
+            match wait_for_preloaded_music(canvas, event_pump, &music_receiver)? {
+                Some(music) => music.and_then(|music| audio_manager.set_music(music)),
+                None => return Ok(GameMode::Exit),
+            }
+        }
+        Some(song_wave_path) => audio_manager.load_music(song_wave_path),
+        None => audio_manager.unload_music(),
+    };
+    if let Err(e) = music_result {
+        println!("Failed to load the music.  The song will be played without it.");
+        println!("Caused by: {:?}", e);
+        audio_manager.unload_music()?;
     }
     let mut game_user_state = GameUserState {
         time: 0.0,
         auto: false,
         speed: 1.0,
+        show_measure: false,
+        show_fps: false,
+        show_judge_counts: false,
+        scrub_preview: false,
+        loop_start: None,
+        loop_end: None,
+        input_offset: config.judge.input_offset,
     };
 
-    // File watcher
-    let (file_change_sender, file_change_receiver) = mpsc::channel();
-    let _watcher = match notify::watcher(file_change_sender, Duration::from_millis(500)) {
-        Ok(mut watcher) => {
-            if let Err(e) = watcher.watch(&tja_file_name, RecursiveMode::NonRecursive) {
-                println!(
-                    "Failed to create file watcher.  The file will not be reloaded automatically."
-                );
+    let _joystick = open_joystick(joystick_subsystem);
+
+    let replay = if config.replay.play {
+        match Replay::load(&config.replay.file) {
+            Ok(replay) => Some(replay),
+            Err(e) => {
+                println!("Failed to load replay file.  The live input will be used instead.");
                 println!("Caused by: {:?}", e);
-            } else {
-                println!("Start watching {:?}", &tja_file_name);
+                None
             }
-            Some(watcher)
-        }
-        Err(e) => {
-            println!(
-                "Failed to create file watcher.  The file will not be reloaded automatically."
-            );
-            println!("Caused by: {:?}", e);
-            None
         }
+    } else {
+        None
     };
+    // Kept across the pauses, so that the whole play is saved
+    let mut recording = Replay::default();
+
+    // File watchers
+    let (file_change_sender, file_change_receiver) = mpsc::channel();
+    let _watcher = watch_file(&tja_file_name, file_change_sender);
+    let (config_change_sender, config_change_receiver) = mpsc::channel();
+    let _config_watcher = watch_file(CONFIG_FILE_NAME, config_change_sender);
 
     'entireLoop: loop {
         loop {
@@ -109,6 +210,7 @@ where
                 audio_manager,
                 assets,
                 &file_change_receiver,
+                &config_change_receiver,
                 &song,
                 game_user_state,
             )? {
@@ -117,6 +219,27 @@ where
                     game_user_state = new_state;
                     break;
                 }
+                PauseBreak::Calibrate(new_state) => {
+                    game_user_state = new_state;
+                    match calibrate(
+                        config,
+                        canvas,
+                        event_pump,
+                        timer_subsystem,
+                        audio_manager,
+                        assets,
+                    )? {
+                        CalibrationBreak::Exit => break 'entireLoop Ok(GameMode::Exit),
+                        CalibrationBreak::Done(Some(input_offset)) => {
+                            println!("Input offset is set to {:.3} seconds", input_offset);
+                            game_user_state.input_offset = input_offset;
+                            if let Err(e) = save_input_offset(input_offset) {
+                                println!("Failed to save the input offset: {:?}", e);
+                            }
+                        }
+                        CalibrationBreak::Done(None) => {}
+                    }
+                }
                 PauseBreak::Reload => {
                     match load_tja_from_file(&tja_file_name)
                         .map_err(|e| new_tja_error("Failed to load tja file", e))
@@ -124,12 +247,24 @@ where
                             Some(..) => Ok(song),
                             None => Err(no_score_in_tja()),
                         }) {
-                        Ok(new_song) => song = new_song,
+                        Ok(new_song) => {
+                            song = new_song;
+                            show_song_title(canvas, &song)?;
+                        }
                         Err(e) => {
                             println!("Failed to load tja file: {:?}", e);
                         }
                     };
                 }
+                PauseBreak::ReloadConfig => match get_config() {
+                    Ok(new_config) => {
+                        config.update_runtime_settings(new_config);
+                        apply_config(config, canvas, audio_manager, assets)?;
+                        game_user_state.input_offset = config.judge.input_offset;
+                        println!("Reloaded {}", CONFIG_FILE_NAME);
+                    }
+                    Err(e) => println!("Failed to reload {}: {:?}", CONFIG_FILE_NAME, e),
+                },
             }
         }
         let score = song.score.as_ref().ok_or_else(no_score_in_tja)?;
@@ -142,15 +277,123 @@ where
             audio_manager,
             assets,
             score,
+            replay.as_ref(),
+            &mut recording,
             &mut game_user_state,
         )? {
             GameBreak::Exit => break Ok(GameMode::Exit),
+            GameBreak::Finish(result) => {
+                audio_manager.pause()?;
+                break Ok(GameMode::Result(result));
+            }
             GameBreak::Escape => {}
             GameBreak::Pause(request_time) => game_user_state.time = request_time,
         }
     }
 }
 
+/// Watch `path`, sending the changes to `sender`.  Failure is not fatal.
+fn watch_file<P>(
+    path: P,
+    sender: mpsc::Sender<notify::DebouncedEvent>,
+) -> Option<notify::RecommendedWatcher>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    match notify::watcher(sender, Duration::from_millis(500)) {
+        Ok(mut watcher) => {
+            if let Err(e) = watcher.watch(&path, RecursiveMode::NonRecursive) {
+                println!(
+                    "Failed to create file watcher.  The file will not be reloaded automatically."
+                );
+                println!("Caused by: {:?}", e);
+            } else {
+                println!("Start watching {:?}", &path);
+            }
+            Some(watcher)
+        }
+        Err(e) => {
+            println!(
+                "Failed to create file watcher.  The file will not be reloaded automatically."
+            );
+            println!("Caused by: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Show the title of the song, followed by the subtitle unless it is suppressed, in the window
+/// title.  There is no font to draw them in the window.
+fn show_song_title(canvas: &mut WindowCanvas, song: &Song) -> Result<(), TaikoError> {
+    let title = match &song.title {
+        Some(title) => title,
+        None => return Ok(()),
+    };
+    let title = match song.subtitle.as_ref().and_then(|s| s.text_in_game()) {
+        Some(subtitle) => format!("{} {}", title, subtitle),
+        None => title.clone(),
+    };
+    canvas
+        .window_mut()
+        .set_title(&title)
+        .map_err(|e| new_sdl_error("Failed to set the window title", e.to_string()))
+}
+
+/// How often the window events are handled while the music is being decoded
+const PRELOAD_POLL_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Wait for the music decoded by `AudioManager::preload_music`, handling the window events in
+/// the meantime so that the window keeps responding.  Returns `None` if the window is closed.
+fn wait_for_preloaded_music(
+    canvas: &mut WindowCanvas,
+    event_pump: &mut EventPump,
+    music_receiver: &Receiver<Result<PreloadedMusic, TaikoError>>,
+) -> Result<Option<Result<PreloadedMusic, TaikoError>>, TaikoError> {
+    loop {
+        for event in event_pump.poll_iter() {
+            handle_window_event(canvas, &event)?;
+            if let Event::Quit { .. } = event {
+                return Ok(None);
+            }
+        }
+        match music_receiver.recv_timeout(PRELOAD_POLL_INTERVAL) {
+            Ok(music) => return Ok(Some(music)),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Ok(Some(Err(TaikoError {
+                    message: "The thread decoding the music has been stopped".to_string(),
+                    cause: TaikoErrorCause::None,
+                })))
+            }
+        }
+    }
+}
+
+/// Apply the reloaded settings to the existing window and sounds, without re-creating them.
+fn apply_config(
+    config: &TaikoConfig,
+    canvas: &mut WindowCanvas,
+    audio_manager: &AudioManager<AutoEvent>,
+    assets: &mut Assets,
+) -> Result<(), TaikoError> {
+    let (width, height) = (config.window.width, config.window.height);
+    if canvas.window().size() != (width, height) {
+        if let Err(e) = canvas.window_mut().set_size(width, height) {
+            println!("Failed to resize the window");
+            println!("Caused by: {:?}", e);
+        }
+        fit_playfield_to_window(canvas)?;
+    }
+    let volume = config.volume.se / 100.0;
+    assets.chunks.sound_don.set_volume(volume);
+    assets.chunks.sound_ka.set_volume(volume);
+    if let Some(sound_balloon) = &mut assets.chunks.sound_balloon {
+        sound_balloon.set_volume(volume);
+    }
+    audio_manager.set_music_volume(config.volume.song / 100.0)?;
+    audio_manager.set_clip_threshold(config.audio.clip_threshold)
+}
+
 #[allow(clippy::too_many_arguments)]
 fn play(
     config: &TaikoConfig,
@@ -161,29 +404,36 @@ fn play(
     audio_manager: &AudioManager<AutoEvent>,
     assets: &mut Assets,
     score: &Score,
+    replay: Option<&Replay>,
+    recording: &mut Replay,
     game_user_state: &mut GameUserState,
 ) -> Result<GameBreak, TaikoError> {
-    let mut game_manager = GameManager::new(score);
-    let mut sound_effect_event_watch = setup_sound_effect(event_subsystem, audio_manager, assets);
+    let mut sound_effect_event_watch =
+        setup_sound_effect(event_subsystem, audio_manager, assets, config);
     sound_effect_event_watch.set_activated(!game_user_state.auto);
 
-    audio_manager.sound_effect_receiver.try_iter().count(); // Consume all
     audio_manager.set_play_speed(game_user_state.speed)?;
-    audio_manager.seek(game_user_state.time)?;
+    let mut replay_player = replay.map(ReplayPlayer::new);
     let mut auto_sent_pointer = 0;
-    audio_manager.clear_play_schedules()?;
-    audio_manager.add_play_schedules(generate_audio_schedules(
+    let mut game_manager = start_playback(
+        audio_manager,
         assets,
-        &game_manager.score,
+        score,
+        replay_player.as_mut(),
+        recording,
         &mut auto_sent_pointer,
-    ))?;
+        game_user_state.time,
+    )?;
     audio_manager.set_play_scheduled(game_user_state.auto)?;
     audio_manager.play()?;
 
     // TODO Gotta wait until seek completes and it starts to play
 
-    loop {
-        if let Some(res) = game_loop(
+    let mut fps_counter = FpsCounter::default();
+    let mut frame_limiter = FrameLimiter::new(config.window.fps_cap());
+
+    let res = loop {
+        match game_loop(
             config,
             canvas,
             event_pump,
@@ -194,11 +444,62 @@ fn play(
             &mut game_manager,
             &mut sound_effect_event_watch,
             &mut auto_sent_pointer,
-            &mut game_user_state.auto,
+            replay_player.as_mut(),
+            recording,
+            game_user_state,
+            &mut fps_counter,
+            &mut frame_limiter,
         )? {
-            break Ok(res);
+            FrameResult::Continue => {}
+            FrameResult::Loop(time) => {
+                game_manager = start_playback(
+                    audio_manager,
+                    assets,
+                    score,
+                    replay_player.as_mut(),
+                    recording,
+                    &mut auto_sent_pointer,
+                    time,
+                )?;
+            }
+            FrameResult::Break(res) => break res,
+        }
+    };
+
+    // A replay being played is not recorded again
+    if config.replay.record && replay.is_none() {
+        if let Err(e) = recording.save(&config.replay.file) {
+            println!("Failed to save replay file: {:?}", e);
         }
     }
+    Ok(res)
+}
+
+/// Start playing from `time` with a new judge, both at the start of a play and at every loop.
+fn start_playback(
+    audio_manager: &AudioManager<AutoEvent>,
+    assets: &Assets,
+    score: &Score,
+    replay_player: Option<&mut ReplayPlayer>,
+    recording: &mut Replay,
+    auto_sent_pointer: &mut usize,
+    time: f64,
+) -> Result<GameManager, TaikoError> {
+    let game_manager = GameManager::new(score);
+    if let Some(replay_player) = replay_player {
+        replay_player.seek(time);
+    }
+    recording.rewind(time);
+    audio_manager.sound_effect_receiver.try_iter().count(); // Consume all
+    audio_manager.seek(time)?;
+    *auto_sent_pointer = auto_sent_pointer_at(&game_manager.score, time);
+    audio_manager.clear_play_schedules()?;
+    audio_manager.add_play_schedules(generate_audio_schedules(
+        assets,
+        &game_manager.score,
+        auto_sent_pointer,
+    ))?;
+    Ok(game_manager)
 }
 
 // TODO too many parameters
@@ -214,61 +515,118 @@ fn game_loop(
     game_manager: &mut GameManager,
     sound_effect_event_watch: &mut EventWatch<SoundEffectCallback>,
     auto_sent_pointer: &mut usize,
-    auto: &mut bool,
-) -> Result<Option<GameBreak>, TaikoError> {
+    replay_player: Option<&mut ReplayPlayer>,
+    recording: &mut Replay,
+    game_user_state: &mut GameUserState,
+    fps_counter: &mut FpsCounter,
+    frame_limiter: &mut FrameLimiter,
+) -> Result<FrameResult, TaikoError> {
     let music_position = audio_manager.music_position()?;
     let sdl_timestamp = timer_subsystem.ticks();
 
+    if let (Some(music_position), Some((start, end))) =
+        (music_position, game_user_state.loop_section())
+    {
+        if music_position >= end {
+            return Ok(FrameResult::Loop(start));
+        }
+    }
+
+    let mut hits = Vec::new();
     for event in event_pump.poll_iter() {
+        handle_window_event(canvas, &event)?;
         match event {
-            Event::Quit { .. } => return Ok(Some(GameBreak::Exit)),
+            Event::Quit { .. } => return Ok(FrameResult::Break(GameBreak::Exit)),
             Event::KeyDown {
                 repeat: false,
                 keycode: Some(keycode),
                 timestamp,
                 keymod,
                 ..
-            } => match keycode {
-                Keycode::Q => return Ok(Some(GameBreak::Escape)),
-                Keycode::Z
-                | Keycode::X
-                | Keycode::Slash
-                | Keycode::Underscore
-                | Keycode::Backslash
-                | Keycode::A
-                | Keycode::S
-                | Keycode::Colon
-                | Keycode::RightBracket => {
-                    if !*auto {
-                        process_key_event(
-                            keycode,
-                            game_manager,
-                            music_position,
-                            timestamp,
-                            sdl_timestamp,
-                        );
+            } => match (keycode, config.key.color(keycode)) {
+                (_, Some(color)) => {
+                    if !game_user_state.auto {
+                        hits.push((timestamp, color, config.key.side(keycode)));
                     }
                 }
-                Keycode::Space => {
+                (Keycode::Q, _) => return Ok(FrameResult::Break(GameBreak::Escape)),
+                (Keycode::Space, _) => {
                     if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
-                        return Ok(Some(GameBreak::Pause(music_position.unwrap_or(0.0))));
+                        return Ok(FrameResult::Break(GameBreak::Pause(
+                            music_position.unwrap_or(0.0),
+                        )));
                     }
                 }
-                Keycode::F1 => {
-                    *auto = !*auto;
-                    audio_manager.set_play_scheduled(*auto)?;
-                    sound_effect_event_watch.set_activated(!*auto);
+                (Keycode::F1, _) => {
+                    game_user_state.auto = !game_user_state.auto;
+                    audio_manager.set_play_scheduled(game_user_state.auto)?;
+                    sound_effect_event_watch.set_activated(!game_user_state.auto);
+                }
+                (Keycode::F2, _) => game_user_state.show_measure = !game_user_state.show_measure,
+                (Keycode::F3, _) => {
+                    game_user_state.show_fps = !game_user_state.show_fps;
+                    *fps_counter = FpsCounter::default();
+                }
+                (Keycode::F4, _) => {
+                    game_user_state.show_judge_counts = !game_user_state.show_judge_counts
                 }
                 _ => {}
             },
+            Event::JoyButtonDown {
+                button_idx,
+                timestamp,
+                ..
+            } => {
+                if let Some(color) = config.joystick.color(button_idx) {
+                    if !game_user_state.auto {
+                        hits.push((timestamp, color, config.joystick.side(button_idx)));
+                    }
+                }
+            }
             _ => {}
         }
     }
-    for response in audio_manager.sound_effect_receiver.try_iter() {
-        game_manager.hit(Some(response.kind.color), response.time);
+    if let Some(replay_player) = replay_player {
+        // The replay already contains every hit, including the ones made by auto play
+        audio_manager.sound_effect_receiver.try_iter().count(); // Consume all
+        if let Some(m) = music_position {
+            replay_player.feed(game_manager, m);
+        }
+    } else {
+        // Events polled in a single frame are processed in the order they actually happened
+        hits.sort_by_key(|&(timestamp, _, _)| timestamp);
+        for (timestamp, color, side) in hits {
+            process_key_event(
+                color,
+                game_manager,
+                recording,
+                music_position.map(|m| game_user_state.judge_position(m)),
+                timestamp,
+                sdl_timestamp,
+            );
+            if let (Some(m), Some(side)) = (music_position, side) {
+                let time = event_music_position(m, timestamp, sdl_timestamp);
+                game_manager.animation_state.drum_hits[side] = Some(DrumHit { time, color });
+            }
+        }
+        for response in audio_manager.sound_effect_receiver.try_iter() {
+            game_manager.hit(Some(response.kind.color), response.time);
+            recording.record(response.kind.color, response.time);
+        }
+        if let Some(m) = music_position {
+            game_manager.hit(None, game_user_state.judge_position(m));
+        }
+    }
+    for _ in 0..game_manager.take_burst_sound_count() {
+        if let Some(sound_balloon) = &assets.chunks.sound_balloon {
+            audio_manager.add_play(sound_balloon)?;
+        }
     }
+
     if let Some(m) = music_position {
-        game_manager.hit(None, m);
+        if game_manager.finished(game_user_state.judge_position(m) - FINISH_DELAY) {
+            return Ok(FrameResult::Break(GameBreak::Finish(game_manager.result())));
+        }
     }
 
     audio_manager.add_play_schedules(generate_audio_schedules(
@@ -277,28 +635,48 @@ fn game_loop(
         auto_sent_pointer,
     ))?;
 
-    draw_game_to_canvas(canvas, assets, score, game_manager, music_position)?;
+    draw_game_to_canvas(
+        config,
+        canvas,
+        assets,
+        score,
+        game_manager,
+        music_position,
+        game_user_state,
+    )?;
+    if game_user_state.show_fps {
+        fps_counter.update(sdl_timestamp);
+        if let Some(fps) = fps_counter.fps() {
+            draw_fps(canvas, assets, fps, config.window.fps_cap())?;
+        }
+    }
 
     canvas.present();
-    if !config.window.vsync {
-        std::thread::sleep(Duration::from_secs_f64(1.0 / config.window.fps));
-    }
+    frame_limiter.wait();
 
-    Ok(None)
+    Ok(FrameResult::Continue)
 }
 
 fn draw_game_to_canvas(
+    config: &TaikoConfig,
     canvas: &mut WindowCanvas,
     assets: &mut Assets,
     score: &Score,
     game_manager: &mut GameManager,
     music_position: Option<f64>,
+    game_user_state: &GameUserState,
 ) -> Result<(), TaikoError> {
     draw_background(canvas, assets).map_err(to_sdl_error("While drawing background"))?;
 
     let gauge = game_manager.game_state.gauge;
     let gauge = clamp(gauge, 0.0, 10000.0) as u32 / 200;
-    draw_gauge(canvas, assets, gauge, 39, 50).map_err(|e| new_sdl_error("Failed to drawr", e))?;
+    draw_gauge(canvas, assets, gauge, 39, 50, music_position.unwrap_or(0.0))
+        .map_err(|e| new_sdl_error("Failed to drawr", e))?;
+    draw_score(
+        canvas,
+        &assets.textures.combo_nummber_white,
+        game_manager.game_state.score,
+    )?;
 
     if let Some(music_position) = music_position {
         let score_rect = game_rect();
@@ -310,32 +688,54 @@ fn draw_game_to_canvas(
                 score_rect,
                 &game_manager.animation_state.branch_state,
             )?;
+            let branch = game_manager.animation_state.branch_state.get();
+            if let Some(gogo_range) = score.gogo_range_at(music_position, branch) {
+                draw_gogo_overlay(canvas, music_position, score_rect, gogo_range)?;
+            }
 
             let bar_lines =
                 BarLineIterator::new(game_manager.score.branches.iter(), score.bar_lines.iter());
             draw_bar_lines(canvas, music_position, bar_lines)?;
 
-            draw_game_notes(canvas, assets, music_position, &game_manager.score)?;
+            draw_game_notes(
+                canvas,
+                assets,
+                music_position,
+                &game_manager.score,
+                game_manager.min_scroll_speed(),
+                config.display.scale_notes_at_edges,
+            )?;
         }
         canvas.set_clip_rect(None);
 
+        draw_drum_hits(
+            canvas,
+            assets,
+            music_position,
+            &game_manager.animation_state.drum_hits,
+        )?;
+
         let flying_notes = game_manager
             .flying_notes(|note| note.time <= music_position - 0.5) // TODO incomplete refactor
             .rev();
         draw_flying_notes(canvas, assets, music_position, flying_notes)?;
 
+        let balloon_bursts =
+            game_manager.balloon_bursts(|burst| burst.time <= music_position - 0.5);
+        draw_balloon_bursts(canvas, assets, music_position, balloon_bursts)?;
+
+        let judge_windows = *game_manager.judge_windows();
         let judge_strs = game_manager
             .judge_strs(|judge| (music_position - judge.time) * 60.0 >= 18.0)
             .rev();
-        draw_judge_strs(canvas, assets, music_position, judge_strs)?;
+        draw_judge_strs(canvas, assets, music_position, &judge_windows, judge_strs)?;
 
         let combo = game_manager.game_state.combo;
-        if let Some(textures) = match () {
-            _ if combo < 10 => None,
-            _ if combo < 50 => Some(&assets.textures.combo_nummber_white),
-            _ if combo < 100 => Some(&assets.textures.combo_nummber_silver),
-            _ => Some(&assets.textures.combo_nummber_gold),
-        } {
+        if let Some(textures) = config.combo.color(combo).map(|color| match color {
+            ComboColor::White => &assets.textures.combo_nummber_white,
+            ComboColor::Silver => &assets.textures.combo_nummber_silver,
+            ComboColor::Gold => &assets.textures.combo_nummber_gold,
+        }) {
             let digits = to_digits(
                 combo
                     .max(0)
@@ -345,6 +745,22 @@ fn draw_game_to_canvas(
             let time = music_position - game_manager.animation_state.last_combo_update;
             draw_combo(canvas, textures, time, digits)?;
         }
+
+        if let Some(count) = game_manager.active_renda_count(music_position) {
+            draw_renda_badge(canvas, assets, count)?;
+        }
+
+        if game_user_state.show_measure {
+            let branch = game_manager.animation_state.branch_state.get();
+            if let Some(index) = score.measure_index(music_position, branch) {
+                let digits = to_digits(index as u64 + 1);
+                draw_measure_number(canvas, &assets.textures.combo_nummber_white, digits)?;
+            }
+        }
+
+        if game_user_state.show_judge_counts {
+            draw_judge_counts(canvas, assets, &game_manager.game_state)?;
+        }
     }
     Ok(())
 }
@@ -353,38 +769,57 @@ struct SoundEffectCallback<'a> {
     sound_don: SoundBuffer,
     sound_ka: SoundBuffer,
     audio_manager: &'a AudioManager<AutoEvent>,
+    key_bindings: KeyBindings,
+    button_bindings: ButtonBindings,
 }
 impl<'a> EventWatchCallback for SoundEffectCallback<'a> {
     fn callback(&mut self, event: Event) {
-        if let Event::KeyDown {
-            keycode: Some(keycode),
-            repeat: false,
-            ..
-        } = event
-        {
-            match keycode {
-                Keycode::X | Keycode::Slash => {
-                    // TODO send error to main thread
-                    let _ = self.audio_manager.add_play(&self.sound_don);
-                }
-                Keycode::A
-                | Keycode::Z
-                | Keycode::Underscore
-                | Keycode::Backslash
-                | Keycode::RightBracket => {
-                    // TODO send error to main thread
-                    let _ = self.audio_manager.add_play(&self.sound_ka);
-                }
-                _ => {}
+        let (color, side) = match event {
+            Event::KeyDown {
+                keycode: Some(keycode),
+                repeat: false,
+                ..
+            } => (
+                self.key_bindings.color(keycode),
+                self.key_bindings.side(keycode),
+            ),
+            Event::JoyButtonDown { button_idx, .. } => (
+                self.button_bindings.color(button_idx),
+                self.button_bindings.side(button_idx),
+            ),
+            _ => (None, None),
+        };
+        let pan = drum_pan(side);
+        match color {
+            Some(NoteColor::Don) => {
+                // TODO send error to main thread
+                let _ = self.audio_manager.add_play_with_pan(&self.sound_don, pan);
             }
+            Some(NoteColor::Ka) => {
+                // TODO send error to main thread
+                let _ = self.audio_manager.add_play_with_pan(&self.sound_ka, pan);
+            }
+            None => {}
         }
     }
 }
 
+/// How far the sound of a hit is panned toward the side of the drum that was hit.
+const DRUM_PAN: f32 = 0.5;
+
+fn drum_pan(side: Option<DrumSide>) -> f32 {
+    match side {
+        Some(DrumSide::Left) => -DRUM_PAN,
+        Some(DrumSide::Right) => DRUM_PAN,
+        None => 0.0,
+    }
+}
+
 fn setup_sound_effect<'au>(
     event_subsystem: &EventSubsystem,
     audio_manager: &'au AudioManager<AutoEvent>,
     assets: &Assets,
+    config: &TaikoConfig,
 ) -> EventWatch<'au, SoundEffectCallback<'au>> {
     let sound_don = assets.chunks.sound_don.clone();
     let sound_ka = assets.chunks.sound_ka.clone();
@@ -392,9 +827,27 @@ fn setup_sound_effect<'au>(
         sound_don,
         sound_ka,
         audio_manager,
+        key_bindings: config.key.clone(),
+        button_bindings: config.joystick.clone(),
     })
 }
 
+fn open_joystick(joystick_subsystem: &JoystickSubsystem) -> Option<Joystick> {
+    let num_joysticks = match joystick_subsystem.num_joysticks() {
+        Ok(num_joysticks) => num_joysticks,
+        Err(e) => {
+            println!("Failed to enumerate joysticks.  Joystick input will be disabled.");
+            println!("Caused by: {:?}", e);
+            return None;
+        }
+    };
+    let joystick = (0..num_joysticks).find_map(|index| joystick_subsystem.open(index).ok());
+    if let Some(joystick) = &joystick {
+        println!("Using joystick {:?}", joystick.name());
+    }
+    joystick
+}
+
 struct BarLineIterator<'a, Branches, BarLines>
 where
     Branches: Iterator<Item = &'a Branch<OfGameState>>,
@@ -454,9 +907,13 @@ pub fn draw_game_notes(
     assets: &Assets,
     music_position: f64,
     score: &ScoreOfGameState,
+    min_scroll_speed: Bpm,
+    scale_at_edges: bool,
 ) -> Result<(), TaikoError> {
     let mut branches = score.branches.iter().rev().peekable();
-    let notes = score.notes.iter().rev();
+    let notes = visible_notes(&score.notes, music_position, min_scroll_speed)
+        .iter()
+        .rev();
 
     // Filter by branch
     let notes = notes.filter(move |note| {
@@ -471,76 +928,73 @@ pub fn draw_game_notes(
     });
 
     // Filter by disappearance
-    let notes = notes.filter_map(|note| {
-        let content = match &note.content {
-            NoteContent::Single(single_note) => single_note
-                .info
-                .visible()
-                .then(|| NoteContent::Single(single_note.clone_with_default())),
-            NoteContent::Renda(RendaContent {
-                kind: RendaKind::Unlimited(renda),
-                end_time,
-                ..
-            }) => Some(NoteContent::Renda(RendaContent {
-                kind: RendaKind::Unlimited(renda.clone_with_default()),
-                end_time: *end_time,
-                info: (),
-            })),
+    let notes = notes
+        .filter(|note| match &note.content {
+            NoteContent::Single(single_note) => single_note.info.visible(),
             NoteContent::Renda(RendaContent {
                 kind: RendaKind::Quota(renda),
-                end_time,
                 ..
-            }) => (!renda.info.finished).then(|| {
-                NoteContent::Renda(RendaContent {
-                    kind: RendaKind::Quota(renda.clone_with_default()),
-                    end_time: *end_time,
-                    info: (),
-                })
-            }),
-        };
-        content.map(|content| just::Note {
-            scroll_speed: note.scroll_speed,
-            time: note.time,
-            branch: note.branch,
-            info: (),
-            content,
+            }) => !renda.info.finished,
+            NoteContent::Renda(..) => true,
         })
-    });
+        .map(|note| note.to_just());
 
-    draw_notes(canvas, assets, music_position, notes)
+    draw_notes(canvas, assets, music_position, notes, scale_at_edges)
 }
 
 fn process_key_event(
-    keycode: Keycode,
+    color: NoteColor,
     game_manager: &mut GameManager,
+    recording: &mut Replay,
     music_position: Option<f64>,
     timestamp: u32,
     sdl_timestamp: u32,
 ) {
-    let color = match keycode {
-        Keycode::X | Keycode::Slash | Keycode::S | Keycode::Colon => NoteColor::Don,
-        Keycode::Z
-        | Keycode::Underscore
-        | Keycode::Backslash
-        | Keycode::A
-        | Keycode::RightBracket => NoteColor::Ka,
-        _ => unreachable!(),
-    };
     if let Some(music_position) = music_position {
-        game_manager.hit(
-            Some(color),
-            // TODO sometimes, timestamp is less than sdl timestamp
-            music_position + (timestamp - sdl_timestamp) as f64 / 1000.0,
-        );
+        let time = event_music_position(music_position, timestamp, sdl_timestamp);
+        game_manager.hit(Some(color), time);
+        recording.record(color, time);
     }
 }
 
+/// Returns the music position at which an event occurred,
+/// given the music position at the time `sdl_timestamp` was taken.
+pub fn event_music_position(music_position: f64, timestamp: u32, sdl_timestamp: u32) -> f64 {
+    // The event may have happened either before or after `sdl_timestamp` was taken
+    music_position + (i64::from(timestamp) - i64::from(sdl_timestamp)) as f64 / 1000.0
+}
+
 fn generate_audio_schedules(
     assets: &Assets,
     score: &ScoreOfGameState,
     auto_sent_pointer: &mut usize,
 ) -> Vec<SoundEffectSchedule<AutoEvent>> {
-    let mut schedules = Vec::new();
+    generate_auto_events(score, auto_sent_pointer)
+        .into_iter()
+        .map(|event| {
+            let chunk = match event.kind.color {
+                NoteColor::Don => &assets.chunks.sound_don,
+                NoteColor::Ka => &assets.chunks.sound_ka,
+            };
+            let volume = match event.kind.size {
+                NoteSize::Small => 1.0,
+                NoteSize::Large => 2.0,
+            };
+            SoundEffectSchedule {
+                timestamp: event.time,
+                source: chunk.new_source(),
+                volume,
+                // The auto play hits the center
+                pan: 0.0,
+                response: event,
+            }
+        })
+        .collect()
+}
+
+/// Returns the hits of the auto play from `auto_sent_pointer` up to the first undetermined branch.
+fn generate_auto_events(score: &ScoreOfGameState, auto_sent_pointer: &mut usize) -> Vec<AutoEvent> {
+    let mut events = Vec::new();
     let mut current_branch = BranchType::Normal;
     let mut branches = score.branches.iter().peekable();
     while *auto_sent_pointer < score.notes.len() {
@@ -560,45 +1014,43 @@ fn generate_audio_schedules(
         }
         match &note.content {
             NoteContent::Single(single_note) => {
-                let chunk = match single_note.kind.color {
-                    NoteColor::Don => &assets.chunks.sound_don,
-                    NoteColor::Ka => &assets.chunks.sound_ka,
-                };
-                let volume = match single_note.kind.size {
-                    NoteSize::Small => 1.0,
-                    NoteSize::Large => 2.0,
-                };
-                schedules.push(SoundEffectSchedule {
-                    timestamp: note.time,
-                    source: chunk.new_source(),
-                    volume,
-                    response: AutoEvent {
-                        time: note.time,
-                        kind: single_note.kind,
-                    },
+                events.push(AutoEvent {
+                    time: note.time,
+                    kind: single_note.kind,
                 });
             }
             NoteContent::Renda(RendaContent { end_time, .. }) => {
-                schedules.extend(
+                events.extend(
                     iterate(note.time, |&x| x + 1.0 / 20.0)
                         .take_while(|t| t < end_time)
-                        .map(|t| SoundEffectSchedule {
-                            timestamp: t,
-                            source: assets.chunks.sound_don.new_source(),
-                            volume: 1.0,
-                            response: AutoEvent {
-                                time: t,
-                                kind: SingleNoteKind {
-                                    color: NoteColor::Don,
-                                    size: NoteSize::Small,
-                                },
+                        .map(|t| AutoEvent {
+                            time: t,
+                            kind: SingleNoteKind {
+                                color: NoteColor::Don,
+                                size: NoteSize::Small,
                             },
                         }),
                 );
             }
         }
     }
-    schedules
+    events
+}
+
+/// Returns the index of the first note whose auto play is not over at `time`, so that seeking
+/// to `time`, even backwards, sends the notes from there again.
+fn auto_sent_pointer_at(score: &ScoreOfGameState, time: f64) -> usize {
+    let mut pointer = score.notes.partition_point(|note| note.time < time);
+    // A renda that started before `time` may still be going on
+    while pointer > 0
+        && matches!(
+            &score.notes[pointer - 1].content,
+            NoteContent::Renda(renda) if time < renda.end_time
+        )
+    {
+        pointer -= 1;
+    }
+    pointer
 }
 
 #[derive(Debug)]
@@ -606,3 +1058,117 @@ pub struct AutoEvent {
     pub time: f64,
     pub kind: SingleNoteKind,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        auto_sent_pointer_at, generate_auto_events, process_key_event, FpsCounter, GameUserState,
+        FINISH_DELAY,
+    };
+    use crate::game_manager::{GameManager, Judge};
+    use crate::replay::Replay;
+    use crate::score_builder::ScoreBuilder;
+    use crate::structs::{NoteColor, NoteSize};
+
+    #[test]
+    fn test_process_key_event_uses_event_timestamp() {
+        let score = ScoreBuilder::new().don(1.0).don(1.1).build();
+        let mut game_manager = GameManager::new(&score);
+        let mut recording = Replay::default();
+        // Both events are polled in the same frame, whose music position is 1.2
+        for &timestamp in &[9000, 9100] {
+            process_key_event(
+                NoteColor::Don,
+                &mut game_manager,
+                &mut recording,
+                Some(1.2),
+                timestamp,
+                9200,
+            );
+        }
+        assert_eq!(recording.hits.len(), 2);
+        let times = game_manager
+            .judge_strs(|_| false)
+            .map(|j| j.time)
+            .collect::<Vec<_>>();
+        assert_eq!(times.len(), 2);
+        assert!((times[0] - 1.0).abs() < 1e-9);
+        assert!((times[1] - 1.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_judge_position_with_input_offset() {
+        let mut game_user_state = GameUserState {
+            time: 0.0,
+            auto: false,
+            speed: 1.0,
+            show_measure: false,
+            show_fps: false,
+            show_judge_counts: false,
+            scrub_preview: false,
+            loop_start: None,
+            loop_end: None,
+            input_offset: 0.2,
+        };
+        let score = ScoreBuilder::new().don(1.0).build();
+        let mut game_manager = GameManager::new(&score);
+        let mut recording = Replay::default();
+        // The tick of a frame must not pass the note that a later hit is going to be judged for
+        game_manager.hit(None, game_user_state.judge_position(1.15));
+        process_key_event(
+            NoteColor::Don,
+            &mut game_manager,
+            &mut recording,
+            Some(game_user_state.judge_position(1.2)),
+            9000,
+            9000,
+        );
+        let judges = game_manager
+            .judge_strs(|_| false)
+            .map(|j| j.judge)
+            .collect::<Vec<_>>();
+        assert!(matches!(judges[..], [Judge::Good]));
+        // The song finishes as late as the judge
+        assert!(!game_manager.finished(game_user_state.judge_position(3.25) - FINISH_DELAY));
+        assert!(game_manager.finished(game_user_state.judge_position(3.35) - FINISH_DELAY));
+
+        // The auto play hits at the exact time of the notes
+        game_user_state.auto = true;
+        assert_eq!(game_user_state.judge_position(1.2), 1.2);
+    }
+
+    #[test]
+    fn test_auto_events_after_seeking_back() {
+        let score = ScoreBuilder::new()
+            .don(1.0)
+            .don(2.0)
+            .renda(3.0, 3.08, NoteSize::Small)
+            .build();
+        let score = GameManager::new(&score).score;
+        let event_times = |time: f64| {
+            let mut auto_sent_pointer = auto_sent_pointer_at(&score, time);
+            generate_auto_events(&score, &mut auto_sent_pointer)
+                .iter()
+                .map(|event| event.time)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(event_times(1.5), vec![2.0, 3.0, 3.05]);
+        assert_eq!(event_times(3.02), vec![3.0, 3.05]);
+        assert!(event_times(3.5).is_empty());
+        // Seeking back sends the earlier notes again
+        assert_eq!(event_times(0.0), vec![1.0, 2.0, 3.0, 3.05]);
+    }
+
+    #[test]
+    fn test_fps_counter() {
+        let mut fps_counter = FpsCounter::default();
+        fps_counter.update(1000);
+        assert_eq!(fps_counter.fps(), None);
+        fps_counter.update(1020);
+        assert_eq!(fps_counter.fps(), Some(50.0));
+        // A single slow frame moves the value only a little
+        fps_counter.update(1120);
+        assert!((fps_counter.fps().unwrap() - 1.0 / 0.028).abs() < 1e-9);
+    }
+}
@@ -1,17 +1,67 @@
 use crate::assets::Assets;
-use crate::errors::{new_sdl_error, SdlError, TaikoError};
-use crate::game_manager::{FlyingNote, Judge, JudgeStr};
+use crate::errors::{new_sdl_canvas_error, new_sdl_error, to_sdl_error, SdlError, TaikoError};
+use crate::game_manager::{
+    BalloonBurst, DrumHit, FlyingNote, GameResult, GameState, Judge, JudgeStr, JudgeWindows, Timing,
+};
 use crate::structs::{
     just::{Note, NoteContent, RendaContent, RendaKind},
-    BarLine, BarLineKind, Bpm, BranchType, NoteColor, NoteSize, SingleNoteKind,
+    typed, BarLine, BarLineKind, Bpm, BranchType, DrumSide, GogoRange, NoteColor, NoteSize,
+    QuotaRendaKind, SingleNoteKind,
 };
+use crate::utils::{to_digits, to_digits_padded};
+use crate::value_with_update_time::Lerp;
 use enum_map::EnumMap;
 use num::clamp;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
 use sdl2::rect::Rect;
 use sdl2::render::WindowCanvas;
+use sdl2::video::FullscreenType;
 use sdl2::{pixels::Color, render::Texture};
 use std::borrow::Borrow;
 
+/// The size of the playfield.  Every drawing coordinate is based on this size.
+pub const PLAYFIELD_SIZE: (u32, u32) = (1920, 1080);
+
+/// Scale the playfield to fit the actual drawable size of the window.
+/// The aspect ratio is preserved, and the remaining area is letterboxed.
+pub fn fit_playfield_to_window(canvas: &mut WindowCanvas) -> Result<(), TaikoError> {
+    let (width, height) = PLAYFIELD_SIZE;
+    canvas
+        .set_logical_size(width, height)
+        .map_err(|e| new_sdl_canvas_error("Failed to scale the playfield", e))
+}
+
+/// Handle the events on the window itself, which are common to every screen:
+/// the playfield is refitted when the window is resized, and F11 toggles fullscreen.
+pub fn handle_window_event(canvas: &mut WindowCanvas, event: &Event) -> Result<(), TaikoError> {
+    match event {
+        Event::Window {
+            win_event: WindowEvent::Resized(..) | WindowEvent::SizeChanged(..),
+            ..
+        } => fit_playfield_to_window(canvas),
+        Event::KeyDown {
+            keycode: Some(Keycode::F11),
+            repeat: false,
+            ..
+        } => {
+            let window = canvas.window_mut();
+            let fullscreen_type = match window.fullscreen_state() {
+                FullscreenType::Off => FullscreenType::Desktop,
+                _ => FullscreenType::Off,
+            };
+            window
+                .set_fullscreen(fullscreen_type)
+                .map_err(|e| new_sdl_error("Failed to toggle fullscreen", e))?;
+            fit_playfield_to_window(canvas)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The width and height of a note texture.
+const NOTE_SIZE: u32 = 195;
+
 pub fn game_rect() -> Rect {
     Rect::new(498, 288, 1422, 195)
 }
@@ -19,11 +69,12 @@ pub fn game_rect() -> Rect {
 pub fn draw_background(canvas: &mut WindowCanvas, assets: &Assets) -> Result<(), SdlError> {
     canvas.set_draw_color(Color::RGBA(20, 20, 20, 0));
     canvas.clear();
-    canvas.copy(
-        &assets.textures.background,
-        None,
-        Some(Rect::new(0, 0, 1920, 1080)),
-    )?;
+    let (width, height) = PLAYFIELD_SIZE;
+    let background = assets
+        .song_background
+        .as_ref()
+        .unwrap_or(&assets.textures.background);
+    canvas.copy(background, None, Some(Rect::new(0, 0, width, height)))?;
     Ok(())
 }
 
@@ -61,7 +112,6 @@ pub fn draw_branch_overlay(
     score_rect: Rect,
     bs: &BranchAnimationState,
 ) -> Result<(), TaikoError> {
-    // TODO color for master course is wrong
     canvas.set_blend_mode(sdl2::render::BlendMode::Add);
     canvas.set_draw_color(interpolate_color(
         branch_overlay_color(bs.branch_before),
@@ -75,11 +125,37 @@ pub fn draw_branch_overlay(
     Ok(())
 }
 
+/// Go-go time effect: the lane glows orange, fading in at the start and pulsing twice a second.
+pub fn draw_gogo_overlay(
+    canvas: &mut WindowCanvas,
+    music_position: f64,
+    score_rect: Rect,
+    gogo_range: &GogoRange,
+) -> Result<(), TaikoError> {
+    let elapsed = music_position - gogo_range.start_time;
+    let fade_in = clamp(elapsed * 60.0 / 10.0, 0.0, 1.0);
+    let pulse = 1.0 - (elapsed * 2.0).rem_euclid(1.0);
+    let t = fade_in * (0.6 + 0.4 * pulse);
+    canvas.set_blend_mode(sdl2::render::BlendMode::Add);
+    canvas.set_draw_color(interpolate_color(
+        Color::RGB(0, 0, 0),
+        Color::RGB(90, 40, 0),
+        t,
+    ));
+    canvas
+        .fill_rect(score_rect)
+        .map_err(|e| new_sdl_error("Failed to draw go-go overlay", e))?;
+    canvas.set_blend_mode(sdl2::render::BlendMode::None);
+    Ok(())
+}
+
+/// The color added to the lane for each branch.
+/// Since they are blended additively, the normal branch adds nothing.
 fn branch_overlay_color(branch_type: BranchType) -> Color {
     match branch_type {
         BranchType::Normal => Color::RGB(0, 0, 0),
         BranchType::Expert => Color::RGB(8, 38, 55),
-        BranchType::Master => Color::RGB(58, 0, 53),
+        BranchType::Master => Color::RGB(46, 0, 70),
     }
 }
 
@@ -93,7 +169,7 @@ where
 {
     let mut sorted_bar_lines = EnumMap::<_, Vec<_>>::new();
     for bar_line in bar_lines {
-        let x = get_x(music_position, bar_line.time, bar_line.scroll_speed) as i32;
+        let x = get_x_clamped(music_position, bar_line.time, bar_line.scroll_speed);
         if (0..=2000).contains(&x) {
             sorted_bar_lines[bar_line.kind].push(Rect::new(x + 96, 288, 3, 195));
         }
@@ -115,6 +191,7 @@ pub fn draw_notes<I, N>(
     assets: &Assets,
     music_position: f64,
     notes: I,
+    scale_at_edges: bool,
 ) -> Result<(), TaikoError>
 where
     I: Iterator<Item = N>,
@@ -122,10 +199,14 @@ where
 {
     for note in notes {
         let note = note.borrow();
+        if !note_on_screen(music_position, note) {
+            continue;
+        }
         match note.content {
             NoteContent::Single(single_note) => {
-                let x = get_x(music_position, note.time, note.scroll_speed);
-                draw_note(canvas, assets, &single_note.kind, x as i32, 288)?;
+                let x = get_x_clamped(music_position, note.time, note.scroll_speed);
+                let scale = if scale_at_edges { edge_scale(x) } else { 1.0 };
+                draw_note_scaled(canvas, assets, &single_note.kind, x, 288, scale)?;
             }
             NoteContent::Renda(RendaContent {
                 end_time,
@@ -139,9 +220,8 @@ where
                         &assets.textures.renda_large_right,
                     ),
                 };
-                // TODO coordinates calculations may lead to overflows
-                let xs = get_x(music_position, note.time, note.scroll_speed) as i32;
-                let xt = get_x(music_position, end_time, note.scroll_speed) as i32;
+                let xs = get_x_clamped(music_position, note.time, note.scroll_speed);
+                let xt = get_x_clamped(music_position, end_time, note.scroll_speed);
                 canvas
                     .copy(
                         texture_right,
@@ -149,13 +229,16 @@ where
                         Rect::new(xt + 97, 288, 195 - 97, 195),
                     )
                     .map_err(|e| new_sdl_error("Failed to draw renda right", e))?;
-                canvas
-                    .copy(
-                        texture_right,
-                        Rect::new(0, 0, 97, 195),
-                        Rect::new(xs + 97, 288, (xt - xs) as u32, 195),
-                    )
-                    .map_err(|e| new_sdl_error("Failed to draw renda center", e))?;
+                // The body is degenerate if the renda is too short or scrolls backward
+                if xt > xs {
+                    canvas
+                        .copy(
+                            texture_right,
+                            Rect::new(0, 0, 97, 195),
+                            Rect::new(xs + 97, 288, (xt - xs) as u32, 195),
+                        )
+                        .map_err(|e| new_sdl_error("Failed to draw renda center", e))?;
+                }
                 canvas
                     .copy(texture_left, None, Rect::new(xs, 288, 195, 195))
                     .map_err(|e| new_sdl_error("Failed to draw renda left", e))?;
@@ -166,7 +249,7 @@ where
                 ..
             }) => {
                 let display_time = num::clamp(music_position, note.time, end_time);
-                let x = get_x(music_position, display_time, note.scroll_speed) as i32;
+                let x = get_x_clamped(music_position, display_time, note.scroll_speed);
                 canvas
                     .copy(
                         &assets.textures.renda_left,
@@ -180,6 +263,72 @@ where
     Ok(())
 }
 
+/// Scale of a note whose left end is at `x`, which grows from 0 to 1 while the note enters the
+/// playfield from the right edge and shrinks back to 0 while it leaves from the left edge.
+fn edge_scale(x: i32) -> f64 {
+    let width = PLAYFIELD_SIZE.0 as f64;
+    let x = x as f64;
+    clamp(((width - x) / 195.0).min((x + 195.0) / 195.0), 0.0, 1.0)
+}
+
+/// Returns the notes, sorted by time, that may be inside the playfield at `music_position` when
+/// no note scrolls slower than `min_scroll_speed`.  A slower note is visible from farther away,
+/// so a note stopped by a zero speed makes the window span the whole score.  Some of the notes
+/// can still be off the screen, so `note_on_screen` has to be checked for each.
+pub fn visible_notes<T: typed::AdditionalInfo>(
+    notes: &[typed::Note<T>],
+    music_position: f64,
+    min_scroll_speed: Bpm,
+) -> &[typed::Note<T>] {
+    let judge_x = get_x(0.0, 0.0, min_scroll_speed);
+    let pixels_per_second = get_x(0.0, 1.0, min_scroll_speed) - judge_x;
+    let reach = f64::max(
+        PLAYFIELD_SIZE.0 as f64 - judge_x,
+        judge_x + NOTE_SIZE as f64,
+    );
+    let radius = reach / pixels_per_second;
+
+    let end = notes.partition_point(|note| note.time <= music_position + radius);
+    let mut start = notes.partition_point(|note| note.time < music_position - radius);
+    // A renda started before the window may still reach into it.  The notes in a branch do not
+    // overlap, so it is enough to look back to the last note of every branch.
+    let mut found = EnumMap::<BranchType, bool>::default();
+    while start > 0 && !found.values().all(|&f| f) {
+        start -= 1;
+        match notes[start].branch {
+            Some(branch) => found[branch] = true,
+            None => break,
+        }
+    }
+    &notes[start..end]
+}
+
+/// Whether any part of the note, including the body of a renda, is inside the playfield.
+pub fn note_on_screen<T: typed::AdditionalInfo>(
+    music_position: f64,
+    note: &typed::Note<T>,
+) -> bool {
+    let (start_time, end_time) = match &note.content {
+        typed::NoteContent::Single(..) => (note.time, note.time),
+        typed::NoteContent::Renda(typed::RendaContent {
+            end_time,
+            kind: typed::RendaKind::Unlimited(..),
+            ..
+        }) => (note.time, *end_time),
+        typed::NoteContent::Renda(typed::RendaContent {
+            end_time,
+            kind: typed::RendaKind::Quota(..),
+            ..
+        }) => {
+            let display_time = clamp(music_position, note.time, *end_time);
+            (display_time, display_time)
+        }
+    };
+    let xs = get_x(music_position, start_time, note.scroll_speed);
+    let xt = get_x(music_position, end_time, note.scroll_speed);
+    xs.min(xt) < PLAYFIELD_SIZE.0 as f64 && xs.max(xt) + NOTE_SIZE as f64 > 0.0
+}
+
 pub fn draw_note(
     canvas: &mut WindowCanvas,
     assets: &Assets,
@@ -187,6 +336,20 @@ pub fn draw_note(
     x: i32,
     y: i32,
 ) -> Result<(), TaikoError> {
+    draw_note_scaled(canvas, assets, kind, x, y, 1.0)
+}
+
+/// Draw a note scaled around its center, whose unscaled top left corner is at `(x, y)`.
+fn draw_note_scaled(
+    canvas: &mut WindowCanvas,
+    assets: &Assets,
+    kind: &SingleNoteKind,
+    x: i32,
+    y: i32,
+    scale: f64,
+) -> Result<(), TaikoError> {
+    let size = (195.0 * scale) as i32;
+    let (x, y) = (x - (size - 195) / 2, y - (size - 195) / 2);
     let texture = match kind.color {
         NoteColor::Don => match kind.size {
             NoteSize::Small => &assets.textures.note_don,
@@ -198,7 +361,7 @@ pub fn draw_note(
         },
     };
     canvas
-        .copy(texture, None, Rect::new(x, y, 195, 195))
+        .copy(texture, None, Rect::new(x, y, size as u32, size as u32))
         .map_err(|e| new_sdl_error("Failed to draw a note", e))
 }
 
@@ -219,7 +382,81 @@ where
             let x = 521.428 + 19.4211 * t + 1.75748 * t * t - 0.035165 * t * t * t;
             let y = 288.4 - 44.303 * t + 0.703272 * t * t + 0.0368848 * t * t * t
                 - 0.000542067 * t * t * t * t;
-            draw_note(canvas, assets, &note.kind, x as i32, y as i32)?;
+            let scale = if note.double_hit { 1.3 } else { 1.0 };
+            draw_note_scaled(canvas, assets, &note.kind, x as i32, y as i32, scale)?;
+        }
+    }
+    Ok(())
+}
+
+/// Highlight the half of the drum face hit last on each side, fading out in 6 frames.
+pub fn draw_drum_hits(
+    canvas: &mut WindowCanvas,
+    assets: &mut Assets,
+    music_position: f64,
+    drum_hits: &EnumMap<DrumSide, Option<DrumHit>>,
+) -> Result<(), TaikoError> {
+    for (side, hit) in drum_hits {
+        let hit = match hit {
+            Some(hit) => hit,
+            None => continue,
+        };
+        let t = (music_position - hit.time) * 60.0;
+        if !(0.0..6.0).contains(&t) {
+            continue;
+        }
+        let texture = match hit.color {
+            NoteColor::Don => &mut assets.textures.drum_don,
+            NoteColor::Ka => &mut assets.textures.drum_ka,
+        };
+        let texture = match texture {
+            Some(texture) => texture,
+            None => continue,
+        };
+        texture.set_alpha_mod(((6.0 - t) / 6.0 * 255.0) as u8);
+        // The drum face is drawn at (335, 296) in 180x180
+        let x = match side {
+            DrumSide::Left => 0,
+            DrumSide::Right => 90,
+        };
+        canvas
+            .copy(
+                texture,
+                Rect::new(x, 0, 90, 180),
+                Rect::new(335 + x, 296, 90, 180),
+            )
+            .map_err(|e| new_sdl_error("Failed to draw the drum face", e))?;
+    }
+    Ok(())
+}
+
+/// Draw the notes scattering from the judge position when a balloon or a kusudama bursts.
+pub fn draw_balloon_bursts<'a, I>(
+    canvas: &mut WindowCanvas,
+    assets: &Assets,
+    music_position: f64,
+    bursts: I,
+) -> Result<(), TaikoError>
+where
+    I: Iterator<Item = &'a BalloonBurst>,
+{
+    const PIECE_COUNT: usize = 8;
+    let kind = SingleNoteKind {
+        color: NoteColor::Don,
+        size: NoteSize::Small,
+    };
+    for burst in bursts {
+        // ends in 0.5 seconds
+        let t = clamp((music_position - burst.time) / 0.5, 0.0, 1.0);
+        let (distance, scale) = match burst.kind {
+            QuotaRendaKind::Balloon => (300.0, 0.5),
+            QuotaRendaKind::Potato => (450.0, 0.7),
+        };
+        for i in 0..PIECE_COUNT {
+            let angle = 2.0 * std::f64::consts::PI * i as f64 / PIECE_COUNT as f64;
+            let x = 520.0 + angle.cos() * distance * t;
+            let y = 288.0 + angle.sin() * distance * t;
+            draw_note_scaled(canvas, assets, &kind, x as i32, y as i32, scale * (1.0 - t))?;
         }
     }
     Ok(())
@@ -229,6 +466,7 @@ pub fn draw_judge_strs<'a, I>(
     canvas: &mut WindowCanvas,
     assets: &mut Assets,
     music_position: f64,
+    judge_windows: &JudgeWindows,
     judge_strs: I,
 ) -> Result<(), TaikoError>
 where
@@ -251,10 +489,36 @@ where
         canvas
             .copy(texture, None, Some(Rect::new(552, y as i32, 135, 90)))
             .map_err(|e| new_sdl_error("Failed to draw judge str", e))?;
+        if let Some(timing) = judge.timing(judge_windows) {
+            draw_timing_indicator(canvas, timing, y as i32, a)?;
+        }
     }
     Ok(())
 }
 
+/// Draw a bar below the judge string, blue if the hit was fast and red if it was slow.
+pub fn draw_timing_indicator(
+    canvas: &mut WindowCanvas,
+    timing: Timing,
+    y: i32,
+    alpha: f64,
+) -> Result<(), TaikoError> {
+    let color = match timing {
+        Timing::Fast => Color::RGB(64, 160, 255),
+        Timing::Slow => Color::RGB(255, 80, 64),
+    };
+    canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
+    canvas.set_draw_color(Color {
+        a: (alpha * 255.0) as u8,
+        ..color
+    });
+    canvas
+        .fill_rect(Rect::new(572, y + 88, 95, 8))
+        .map_err(|e| new_sdl_error("Failed to draw timing indicator", e))?;
+    canvas.set_blend_mode(sdl2::render::BlendMode::None);
+    Ok(())
+}
+
 pub fn draw_combo(
     canvas: &mut WindowCanvas,
     textures: &[Texture],
@@ -284,12 +548,276 @@ pub fn draw_combo(
     Ok(())
 }
 
-pub fn draw_gauge(
+const SCORE_MIN_DIGITS: usize = 7;
+const SCORE_MAX_WIDTH: f64 = 36.0 * 8.0;
+
+/// Returns the left x coordinates and the width of each digit of the score, which is
+/// right-aligned at the top center.  Digits get narrower beyond eight digits so that the score
+/// never grows wider than eight digits.
+fn score_digit_layout(len: usize) -> (Vec<i32>, u32) {
+    let w = (36.0 * len as f64).min(SCORE_MAX_WIDTH) / len as f64;
+    let right = 960.0 + 36.0 * SCORE_MIN_DIGITS as f64 / 2.0;
+    let xs = (0..len)
+        .map(|i| (right - w * (len - i) as f64) as i32)
+        .collect();
+    (xs, w as u32)
+}
+
+/// Draw the score zero-padded to seven digits at the top center.
+pub fn draw_score(
+    canvas: &mut WindowCanvas,
+    textures: &[Texture],
+    score: i64,
+) -> Result<(), TaikoError> {
+    let digits = to_digits_padded(score.max(0) as u64, 10, SCORE_MIN_DIGITS);
+    let (xs, w) = score_digit_layout(digits.len());
+    for (x, t) in xs
+        .into_iter()
+        .zip(digits.iter().map(|&d| &textures[d as usize]))
+    {
+        canvas
+            .copy(t, None, Rect::new(x, 40, w, 50))
+            .map_err(|e| new_sdl_error("Failed to draw score", e))?;
+    }
+    Ok(())
+}
+
+/// Draw the badge with the hit count of the unlimited renda being played, above the judge point.
+pub fn draw_renda_badge(
+    canvas: &mut WindowCanvas,
+    assets: &Assets,
+    count: u64,
+) -> Result<(), TaikoError> {
+    if let Some(renda_badge) = &assets.textures.renda_badge {
+        canvas
+            .copy(renda_badge, None, Rect::new(497, 150, 240, 120))
+            .map_err(|e| new_sdl_error("Failed to draw renda badge", e))?;
+    }
+    let digits = to_digits(count);
+    let x = 617 - 22 * digits.len() as i32;
+    let textures = &assets.textures.combo_nummber_white;
+    for (i, t) in digits.iter().map(|&i| &textures[i as usize]).enumerate() {
+        canvas
+            .copy(t, None, Rect::new(x + 44 * i as i32, 172, 44, 77))
+            .map_err(|e| new_sdl_error("Failed to draw renda count", e))?;
+    }
+    Ok(())
+}
+
+/// Draw the measured frame rate at the top right corner, and the target one below it if any.
+pub fn draw_fps(
+    canvas: &mut WindowCanvas,
+    assets: &Assets,
+    fps: f64,
+    target_fps: Option<f64>,
+) -> Result<(), TaikoError> {
+    let rows = [
+        Some((fps, &assets.textures.combo_nummber_white, 8)),
+        target_fps.map(|target_fps| (target_fps, &assets.textures.combo_nummber_silver, 43)),
+    ];
+    for &(value, textures, y) in rows.iter().flatten() {
+        let digits = to_digits(value.round().max(0.0) as u64);
+        let x = PLAYFIELD_SIZE.0 as i32 - 8 - 22 * digits.len() as i32;
+        for (i, t) in digits.iter().map(|&i| &textures[i as usize]).enumerate() {
+            canvas
+                .copy(t, None, Rect::new(x + 22 * i as i32, y, 22, 31))
+                .map_err(|e| new_sdl_error("Failed to draw fps", e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Draw the measure number at the top left corner of the lane.
+pub fn draw_measure_number(
+    canvas: &mut WindowCanvas,
+    textures: &[Texture],
+    digits: Vec<u32>,
+) -> Result<(), TaikoError> {
+    for (i, t) in digits.iter().map(|&i| &textures[i as usize]).enumerate() {
+        canvas
+            .copy(t, None, Rect::new(506 + 22 * i as i32, 292, 22, 31))
+            .map_err(|e| new_sdl_error("Failed to draw measure number", e))?;
+    }
+    Ok(())
+}
+
+/// Draw the numbers of good, ok and bad judges and the current combo below the lane, at half
+/// the size of those on the result screen.
+pub fn draw_judge_counts(
+    canvas: &mut WindowCanvas,
+    assets: &Assets,
+    game_state: &GameState,
+) -> Result<(), TaikoError> {
+    let textures = &assets.textures;
+    let rows = [
+        (
+            Some(&textures.judge_text_good),
+            game_state.good_count,
+            &textures.combo_nummber_white,
+        ),
+        (
+            Some(&textures.judge_text_ok),
+            game_state.ok_count,
+            &textures.combo_nummber_white,
+        ),
+        (
+            Some(&textures.judge_text_bad),
+            game_state.bad_count,
+            &textures.combo_nummber_white,
+        ),
+        (None, game_state.combo, &textures.combo_nummber_gold),
+    ];
+    for (i, (label, value, digit_textures)) in rows.iter().enumerate() {
+        let y = 500 + 50 * i as i32;
+        if let Some(label) = label {
+            canvas
+                .copy(label, None, Rect::new(726, y, 68, 45))
+                .map_err(|e| new_sdl_error("Failed to draw judge count label", e))?;
+        }
+        let digits = to_digits((*value).max(0) as u64);
+        for (j, t) in digits
+            .iter()
+            .map(|&d| &digit_textures[d as usize])
+            .enumerate()
+        {
+            canvas
+                .copy(t, None, Rect::new(810 + 22 * j as i32, y + 7, 22, 31))
+                .map_err(|e| new_sdl_error("Failed to draw judge count", e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Draw the result of a play: the score, the judge counts, the max combo and the gauge.
+/// The bar under the gauge is gold if the song is cleared, and gray otherwise.
+pub fn draw_result(
+    canvas: &mut WindowCanvas,
+    assets: &mut Assets,
+    result: &GameResult,
+    time: f64,
+) -> Result<(), TaikoError> {
+    draw_background(canvas, assets).map_err(to_sdl_error("While drawing background"))?;
+
+    let game_state = &result.game_state;
+    let gauge = clamp(game_state.gauge, 0.0, 10000.0) as u32 / 200;
+    draw_gauge(canvas, assets, gauge, 39, 50, time)
+        .map_err(|e| new_sdl_error("Failed to draw gauge", e))?;
+    canvas.set_draw_color(if result.cleared() {
+        Color::RGB(255, 200, 0)
+    } else {
+        Color::RGB(96, 96, 96)
+    });
+    canvas
+        .fill_rect(Rect::new(726, 290, 1194, 12))
+        .map_err(|e| new_sdl_error("Failed to draw clear bar", e))?;
+
+    let textures = &assets.textures;
+    let rows = [
+        (None, game_state.score, &textures.combo_nummber_silver),
+        (
+            Some(&textures.judge_text_good),
+            game_state.good_count,
+            &textures.combo_nummber_white,
+        ),
+        (
+            Some(&textures.judge_text_ok),
+            game_state.ok_count,
+            &textures.combo_nummber_white,
+        ),
+        (
+            Some(&textures.judge_text_bad),
+            game_state.bad_count,
+            &textures.combo_nummber_white,
+        ),
+        (None, game_state.max_combo, &textures.combo_nummber_gold),
+    ];
+    for (i, (label, value, digit_textures)) in rows.iter().enumerate() {
+        let y = 340 + 120 * i as i32;
+        if let Some(label) = label {
+            canvas
+                .copy(label, None, Rect::new(726, y, 135, 90))
+                .map_err(|e| new_sdl_error("Failed to draw result label", e))?;
+        }
+        let digits = to_digits((*value).max(0) as u64);
+        for (j, t) in digits
+            .iter()
+            .map(|&d| &digit_textures[d as usize])
+            .enumerate()
+        {
+            canvas
+                .copy(t, None, Rect::new(900 + 52 * j as i32, y + 6, 55, 77))
+                .map_err(|e| new_sdl_error("Failed to draw result number", e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Draw the songs as a column of bars numbered from 1, centered on the selected one.
+pub fn draw_song_list(
     canvas: &mut WindowCanvas,
     assets: &Assets,
+    song_count: usize,
+    selected: usize,
+) -> Result<(), TaikoError> {
+    const VISIBLE_ROWS: i32 = 4;
+    draw_background(canvas, assets).map_err(to_sdl_error("While drawing background"))?;
+    for row in -VISIBLE_ROWS..=VISIBLE_ROWS {
+        let index = selected as i64 + row as i64;
+        if index < 0 || index >= song_count as i64 {
+            continue;
+        }
+        let y = 540 - 40 + row * 100;
+        let color = match row {
+            0 => Color::RGB(255, 220, 60),
+            _ => Color::RGB(60, 60, 60),
+        };
+        canvas.set_draw_color(color);
+        canvas
+            .fill_rect(Rect::new(560, y, 800, 80))
+            .map_err(|e| new_sdl_error("Failed to draw song list", e))?;
+        let textures = &assets.textures.combo_nummber_white;
+        for (i, t) in to_digits(index as u64 + 1)
+            .iter()
+            .map(|&i| &textures[i as usize])
+            .enumerate()
+        {
+            canvas
+                .copy(t, None, Rect::new(590 + 44 * i as i32, y + 2, 44, 77))
+                .map_err(|e| new_sdl_error("Failed to draw song number", e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Draw the calibration screen: a note that flashes on each beat at the judge position,
+/// and the number of taps measured so far in place of the combo.
+pub fn draw_calibration(
+    canvas: &mut WindowCanvas,
+    assets: &Assets,
+    seconds_after_beat: Option<f64>,
+    tap_count: usize,
+) -> Result<(), TaikoError> {
+    draw_background(canvas, assets).map_err(to_sdl_error("While drawing background"))?;
+    if seconds_after_beat.map_or(false, |t| (0.0..0.1).contains(&t)) {
+        let kind = SingleNoteKind {
+            color: NoteColor::Don,
+            size: NoteSize::Small,
+        };
+        draw_note(canvas, assets, &kind, 520, 288)?;
+    }
+    let digits = to_digits(tap_count as u64);
+    draw_combo(canvas, &assets.textures.combo_nummber_white, 1.0, digits)
+}
+
+/// Draw the gauge with `gauge` segments out of `all_count` filled.  A full gauge shines in
+/// rainbow colors flowing along it, and its soul pulses, both animated by `time` in seconds.
+pub fn draw_gauge(
+    canvas: &mut WindowCanvas,
+    assets: &mut Assets,
     gauge: u32,
     clear_count: u32,
     all_count: u32,
+    time: f64,
 ) -> Result<(), String> {
     canvas.copy(
         &assets.textures.gauge_left_base,
@@ -353,32 +881,257 @@ pub fn draw_gauge(
         ),
     )?;
 
+    let full = gauge >= all_count;
+    if full {
+        draw_rainbow_gauge(canvas, assets, clear_count, all_count, max_width, time)?;
+    }
+
+    // The soul beats twice a second while the gauge is full
+    let scale = if full {
+        1.0 + 0.15 * (1.0 - (time * 2.0).rem_euclid(1.0))
+    } else {
+        1.0
+    };
+    let (width, height) = ((71.0 * scale) as u32, (63.0 * scale) as u32);
     canvas.copy(
         &assets.textures.gauge_soul,
         None,
-        Rect::new(1799, 215, 71, 63),
+        Rect::new(
+            1799 - (width as i32 - 71) / 2,
+            215 - (height as i32 - 63) / 2,
+            width,
+            height,
+        ),
     )?;
     Ok(())
 }
 
+/// Add a rainbow color to each filled segment of the gauge, shifted by its position.
+fn draw_rainbow_gauge(
+    canvas: &mut WindowCanvas,
+    assets: &mut Assets,
+    clear_count: u32,
+    all_count: u32,
+    max_width: u32,
+    time: f64,
+) -> Result<(), String> {
+    for i in 0..all_count {
+        let (texture, x, width) = if i < clear_count {
+            (&mut assets.textures.gauge_left_red, 21 * i, 21)
+        } else {
+            let x = 21 * (i - clear_count);
+            let width = 21.min(max_width.saturating_sub(x));
+            (&mut assets.textures.gauge_right_yellow, x, width)
+        };
+        if width == 0 {
+            continue;
+        }
+        texture.set_blend_mode(sdl2::render::BlendMode::Add);
+        let (r, g, b) = rainbow_color(time - i as f64 / all_count as f64).rgb();
+        texture.set_color_mod(r, g, b);
+        let src = Rect::new(x as i32, 0, width, 78);
+        canvas.copy(texture, src, Rect::new(738 + 21 * i as i32, 204, width, 78))?;
+    }
+    for texture in [
+        &mut assets.textures.gauge_left_red,
+        &mut assets.textures.gauge_right_yellow,
+    ]
+    .iter_mut()
+    {
+        texture.set_blend_mode(sdl2::render::BlendMode::Blend);
+        texture.set_color_mod(255, 255, 255);
+    }
+    Ok(())
+}
+
+/// A color going around the hue once for every unit of `t`, dark enough to be added.
+fn rainbow_color(t: f64) -> Color {
+    let h = t.rem_euclid(1.0) * 6.0;
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+    let (r, g, b) = match h as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    let channel = |v: f64| (v * 128.0).round() as u8;
+    Color::RGB(channel(r), channel(g), channel(b))
+}
+
 fn interpolate_color(color_zero: Color, color_one: Color, t: f64) -> Color {
-    let Color {
-        r: r0,
-        g: g0,
-        b: b0,
-        a: a0,
-    } = color_zero;
-    let Color {
-        r: r1,
-        g: g1,
-        b: b1,
-        a: a1,
-    } = color_one;
-    let f = |x, y| clamp(x as f64 * (1.0 - t) + y as f64 * t, 0.0, 255.0) as u8;
-    Color::RGBA(f(r0, r1), f(g0, g1), f(b0, b1), f(a0, a1))
+    color_zero.lerp(&color_one, clamp(t, 0.0, 1.0))
 }
 
+/// Returns the x coordinate of a note at `time`, which moves four beats across the lane.
+/// A zero scroll speed pins the note at the judge position, and a negative one moves it from the
+/// left to the right.  Multiplying by the speed rather than dividing by the beat duration keeps the
+/// zero speed finite.
 fn get_x(music_position: f64, time: f64, scroll_speed: Bpm) -> f64 {
     let diff = time - music_position;
-    520.0 + 1422.0 / 4.0 * diff / scroll_speed.beat_duration()
+    520.0 + 1422.0 / 4.0 * diff * scroll_speed.0 / 60.0
+}
+
+/// Same as `get_x`, but clamped to a range wide enough to cover the playfield,
+/// so that the subsequent calculations in `i32` never overflow.
+/// An undefined position, such as an infinite time at zero speed, is at the judge position.
+fn get_x_clamped(music_position: f64, time: f64, scroll_speed: Bpm) -> i32 {
+    let width = PLAYFIELD_SIZE.0 as f64;
+    match get_x(music_position, time, scroll_speed) {
+        x if x.is_nan() => 520,
+        x => clamp(x, -width, 2.0 * width) as i32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        branch_overlay_color, edge_scale, get_x, get_x_clamped, interpolate_color, note_on_screen,
+        rainbow_color, score_digit_layout, visible_notes, SCORE_MAX_WIDTH,
+    };
+    use crate::score_builder::ScoreBuilder;
+    use crate::structs::{Bpm, BranchType, NoteSize};
+    use sdl2::pixels::Color;
+
+    #[test]
+    fn test_branch_overlay_interpolation_endpoints() {
+        use BranchType::*;
+        for &(before, after) in &[
+            (Normal, Expert),
+            (Expert, Master),
+            (Master, Normal),
+            (Normal, Master),
+        ] {
+            let (x, y) = (branch_overlay_color(before), branch_overlay_color(after));
+            assert_eq!(interpolate_color(x, y, 0.0), x);
+            assert_eq!(interpolate_color(x, y, 1.0), y);
+            assert_eq!(interpolate_color(x, y, -1.0), x);
+            assert_eq!(interpolate_color(x, y, 2.0), y);
+        }
+    }
+
+    #[test]
+    fn test_interpolate_color_halfway() {
+        let x = Color::RGBA(0, 255, 10, 255);
+        let y = Color::RGBA(255, 0, 20, 255);
+        assert_eq!(interpolate_color(x, y, 0.5), Color::RGBA(128, 128, 15, 255));
+    }
+
+    #[test]
+    fn test_rainbow_color() {
+        assert_eq!(rainbow_color(0.0), Color::RGB(128, 0, 0));
+        assert_eq!(rainbow_color(1.0 / 6.0), Color::RGB(128, 128, 0));
+        assert_eq!(rainbow_color(1.0 / 3.0), Color::RGB(0, 128, 0));
+        assert_eq!(rainbow_color(0.75), Color::RGB(64, 0, 128));
+        assert_eq!(rainbow_color(-0.25), Color::RGB(64, 0, 128));
+        assert_eq!(rainbow_color(2.0), rainbow_color(0.0));
+    }
+
+    #[test]
+    fn test_edge_scale() {
+        assert_eq!(edge_scale(1920), 0.0);
+        assert_eq!(edge_scale(1920 - 195), 1.0);
+        assert_eq!(edge_scale(520), 1.0);
+        assert_eq!(edge_scale(0), 1.0);
+        assert_eq!(edge_scale(-195), 0.0);
+        assert!((edge_scale(1920 - 39) - 0.2).abs() < 1e-9);
+        assert!((edge_scale(-156) - 0.2).abs() < 1e-9);
+        assert_eq!(edge_scale(-1920), 0.0);
+    }
+
+    #[test]
+    fn test_get_x_clamped_does_not_overflow() {
+        // A very fast scroll speed, i.e. a very small beat duration
+        let scroll_speed = Bpm(1e300);
+        let xs = get_x_clamped(0.0, -1e10, scroll_speed);
+        let xt = get_x_clamped(0.0, 1e10, scroll_speed);
+        assert_eq!((xs, xt), (-1920, 3840));
+        assert_eq!(xt - xs, 5760);
+        assert_eq!(xt + 97, 3937);
+
+        let x = get_x_clamped(0.0, f64::MAX, Bpm(120.0));
+        assert_eq!(x, 3840);
+    }
+
+    #[test]
+    fn test_get_x_zero_and_negative_scroll() {
+        // One beat ahead at 120 BPM is a quarter of the lane
+        assert_eq!(get_x(0.0, 0.5, Bpm(120.0)), 520.0 + 355.5);
+        assert_eq!(get_x(0.0, 0.5, Bpm(-120.0)), 520.0 - 355.5);
+        assert_eq!(get_x(0.0, -0.5, Bpm(-120.0)), 520.0 + 355.5);
+
+        for &time in &[-10.0, 0.0, 0.5, 10.0] {
+            assert_eq!(get_x(0.0, time, Bpm(0.0)), 520.0);
+            assert_eq!(get_x(0.0, time, Bpm(-0.0)), 520.0);
+        }
+        assert_eq!(get_x_clamped(0.0, f64::INFINITY, Bpm(0.0)), 520);
+        assert_eq!(get_x_clamped(0.0, 1e10, Bpm(-120.0)), -1920);
+    }
+
+    #[test]
+    fn test_long_renda_spanning_screen_is_on_screen() {
+        let score = ScoreBuilder::new()
+            .renda(-10.0, 10.0, NoteSize::Small)
+            .build();
+        let note = &score.notes[0];
+        assert!(note_on_screen(0.0, note));
+        assert!(!note_on_screen(20.0, note));
+        assert!(!note_on_screen(-20.0, note));
+    }
+
+    #[test]
+    fn test_visible_notes() {
+        let mut builder = ScoreBuilder::new()
+            .renda(-50.0, 50.0, NoteSize::Small)
+            .branch(Some(BranchType::Master))
+            .renda(51.0, 59.0, NoteSize::Large)
+            .branch(None);
+        for i in 0..40 {
+            let time = 60.0 + i as f64 * 0.5;
+            builder = builder.scroll_speed(60.0 + i as f64 * 10.0).don(time);
+        }
+        let score = builder.build();
+        let min_scroll_speed = score.min_scroll_speed();
+        assert_eq!(min_scroll_speed.0, 60.0);
+
+        let times = |music_position| {
+            visible_notes(&score.notes, music_position, min_scroll_speed)
+                .iter()
+                .map(|note| note.time)
+                .collect::<Vec<_>>()
+        };
+        // The renda of every branch that started before the window is kept
+        assert_eq!(times(0.0), vec![-50.0]);
+        assert_eq!(times(58.0), vec![-50.0, 51.0, 60.0, 60.5, 61.0, 61.5]);
+
+        // Every note on the screen is in the window
+        for position in (0..800).map(|i| i as f64 * 0.1) {
+            let visible = visible_notes(&score.notes, position, min_scroll_speed);
+            let on_screen = score
+                .notes
+                .iter()
+                .filter(|note| note_on_screen(position, note))
+                .count();
+            let on_screen_in_window = visible
+                .iter()
+                .filter(|note| note_on_screen(position, note))
+                .count();
+            assert_eq!(on_screen, on_screen_in_window, "at {}", position);
+        }
+    }
+
+    #[test]
+    fn test_score_digit_layout() {
+        let (xs, w) = score_digit_layout(7);
+        assert_eq!(w, 36);
+        assert_eq!(xs.first(), Some(&834));
+        assert_eq!(xs.last().unwrap() + w as i32, 1086);
+
+        // The largest score still fits in the width of eight digits, with the same right end
+        let (xs, w) = score_digit_layout(20);
+        assert_eq!(xs.len(), 20);
+        assert!(xs.last().unwrap() + w as i32 <= 1086);
+        assert!((1086 - xs[0]) as f64 <= SCORE_MAX_WIDTH);
+    }
 }
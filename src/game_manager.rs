@@ -19,10 +19,13 @@ impl typed::AdditionalInfo for OfGameState {
     type Branch = BranchState;
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, PartialEq)]
 pub struct SingleNoteInfo {
     pub judge: Option<JudgeOrPassed>,
     gauge_delta: EnumMap<Judge, f64>,
+    /// The time of the hit that judged this large note, awaiting the second hit
+    pub first_hit_time: Option<f64>,
+    pub double_hit: bool,
 }
 impl SingleNoteInfo {
     pub fn visible(&self) -> bool {
@@ -30,17 +33,19 @@ impl SingleNoteInfo {
     }
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, PartialEq)]
 pub struct RendaState {
     pub count: u64,
 }
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, PartialEq)]
 pub struct QuotaRendaState {
     // TODO we don't actually need this field
     pub finished: bool,
+    /// Whether the end of the renda has been accounted in `GameState::balloon_count`
+    pub passed: bool,
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, PartialEq)]
 pub struct BranchState {
     pub determined_branch: Option<BranchType>,
 }
@@ -70,12 +75,21 @@ pub struct GameManager {
     game_state_section: GameState,
     branch_event_pointer: usize,
     branch_event_branch_pointer: usize,
+    gogo_pointer: usize,
+    gogo_branch_pointer: usize,
+    /// The index in `score.notes` and the time of the last hit on a large note, if it may still
+    /// become a double hit
+    double_hit_candidate: Option<(usize, f64)>,
+    judge_windows: JudgeWindows,
+    /// The time after which every note has been passed, or `None` if there is no note
+    finish_time: Option<f64>,
+    min_scroll_speed: Bpm,
 
     pub game_state: GameState,
     pub animation_state: AnimationState,
 }
 
-#[derive(Clone, Copy, Default, Debug, derive_more::Sub)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, derive_more::Sub)]
 pub struct GameState {
     // The following integers are signed integers to enable subtractions
     pub score: i64,
@@ -84,8 +98,13 @@ pub struct GameState {
     pub ok_count: i64,
     pub bad_count: i64,
     pub renda_count: i64,
+    /// Number of quota rendas whose end has passed, whether burst or not
+    pub balloon_count: i64,
+    /// Number of quota rendas hit as many times as their quota
+    pub balloon_burst_count: i64,
 
     pub combo: i64,
+    pub max_combo: i64,
     // f64 has enough precision.  See the test below
     pub gauge: f64,
 }
@@ -99,7 +118,12 @@ impl GameState {
         }
     }
 
-    fn update_with_judge<J: Into<JudgeOrPassed>>(&mut self, note: &mut SingleNote, judge: J) {
+    fn update_with_judge<J: Into<JudgeOrPassed>>(
+        &mut self,
+        note: &mut SingleNote,
+        judge: J,
+        gogo: bool,
+    ) {
         let judge = judge.into();
         let was_none = note.info.judge.is_none();
         note.info.judge = Some(judge);
@@ -111,6 +135,8 @@ impl GameState {
                 Judge::Bad => self.combo = 0,
                 _ => self.combo += 1,
             }
+            self.max_combo = self.max_combo.max(self.combo);
+            self.score += points(judge, gogo);
             self.gauge = clamp(self.gauge + note.info.gauge_delta[judge], 0.0, 10000.0);
         }
     }
@@ -121,8 +147,13 @@ impl GameState {
 pub struct AnimationState {
     flying_notes: VecDeque<FlyingNote>,
     judge_strs: VecDeque<JudgeStr>,
+    balloon_bursts: VecDeque<BalloonBurst>,
+    /// The number of balloon bursts whose sound has not been played yet
+    unplayed_burst_sound_count: usize,
     pub last_combo_update: f64,
     pub branch_state: BranchAnimationState,
+    /// The last hit by the player on each side of the drum
+    pub drum_hits: EnumMap<DrumSide, Option<DrumHit>>,
 }
 
 impl Note {
@@ -136,6 +167,8 @@ impl Note {
                     info: SingleNoteInfo {
                         judge: None,
                         gauge_delta: *gauge_delta,
+                        first_hit_time: None,
+                        double_hit: false,
                     },
                 }),
                 just::NoteContent::Renda(note) => NoteContent::Renda(RendaContent {
@@ -163,14 +196,48 @@ impl Note {
 pub struct FlyingNote {
     pub time: f64,
     pub kind: SingleNoteKind,
+    pub double_hit: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DrumHit {
+    pub time: f64,
+    pub color: NoteColor,
+}
+
+/// A balloon or a kusudama that has just reached its quota.
+pub struct BalloonBurst {
+    pub time: f64,
+    pub kind: QuotaRendaKind,
 }
 
 pub struct JudgeStr {
     pub time: f64,
     pub judge: Judge,
+    /// `note.time - time` at the hit; positive if hit too early, negative if too late
+    pub time_error: f64,
 }
 
-#[derive(Clone, Copy, Debug)]
+impl JudgeStr {
+    /// Returns whether the hit was fast or slow, if it was off by more than the good window of
+    /// `judge_windows`.
+    pub fn timing(&self, judge_windows: &JudgeWindows) -> Option<Timing> {
+        let good_window = judge_windows.windows[Judge::Good];
+        match self.time_error {
+            t if t > good_window => Some(Timing::Fast),
+            t if t < -good_window => Some(Timing::Slow),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timing {
+    Fast,
+    Slow,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum JudgeOrPassed {
     Judge(Judge),
     Passed,
@@ -191,7 +258,7 @@ impl From<JudgeOrPassed> for Judge {
     }
 }
 
-#[derive(Clone, Copy, Debug, Enum)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enum)]
 pub enum Judge {
     Good,
     Ok,
@@ -203,6 +270,68 @@ const GOOD_WINDOW: f64 = 25.0250015258789 / 1000.0;
 const OK_WINDOW: f64 = 75.0750045776367 / 1000.0;
 const BAD_WINDOW: f64 = 108.441665649414 / 1000.0;
 
+/// The maximum distance in seconds between a note and a hit for each judge.
+#[derive(Clone, Copy, Debug)]
+pub struct JudgeWindows {
+    pub windows: EnumMap<Judge, f64>,
+}
+
+impl Default for JudgeWindows {
+    fn default() -> Self {
+        JudgeWindows {
+            windows: enum_map![
+                Judge::Good => GOOD_WINDOW,
+                Judge::Ok => OK_WINDOW,
+                Judge::Bad => BAD_WINDOW,
+            ],
+        }
+    }
+}
+
+impl JudgeWindows {
+    /// Multiply every window by `multiplier`, e.g. to make a lenient mode.
+    pub fn scaled(mut self, multiplier: f64) -> Self {
+        self.windows.values_mut().for_each(|w| *w *= multiplier);
+        self
+    }
+}
+
+/// A large note hit again with the same color within this many seconds becomes a double hit,
+/// which doubles its points.
+const DOUBLE_HIT_WINDOW: f64 = 0.05;
+
+/// Points of a Good; an Ok is worth half of them, and a Bad nothing.
+const GOOD_POINTS: i64 = 1000;
+/// Points are multiplied by this during go-go time.
+const GOGO_MULTIPLIER: f64 = 1.2;
+
+fn points(judge: Judge, gogo: bool) -> i64 {
+    let points = match judge {
+        Judge::Good => GOOD_POINTS,
+        Judge::Ok => GOOD_POINTS / 2,
+        Judge::Bad => 0,
+    };
+    if gogo {
+        (points as f64 * GOGO_MULTIPLIER) as i64
+    } else {
+        points
+    }
+}
+
+/// The gauge needed to clear a song: 39 out of 50 segments, each of which is worth 200.
+const CLEAR_GAUGE: f64 = 7800.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct GameResult {
+    pub game_state: GameState,
+}
+
+impl GameResult {
+    pub fn cleared(&self) -> bool {
+        self.game_state.gauge >= CLEAR_GAUGE
+    }
+}
+
 fn get_gauge_good_delta(score: &just::Score) -> f64 {
     let mut counts = EnumMap::<_, usize>::new();
     for note in &score.notes {
@@ -223,6 +352,10 @@ fn get_gauge_good_delta(score: &just::Score) -> f64 {
 
 impl GameManager {
     pub fn new(score: &just::Score) -> Self {
+        Self::with_judge_windows(score, JudgeWindows::default())
+    }
+
+    pub fn with_judge_windows(score: &just::Score, judge_windows: JudgeWindows) -> Self {
         let good_delta = get_gauge_good_delta(score);
         let gauge_delta = enum_map![
             Judge::Good => good_delta,
@@ -243,6 +376,7 @@ impl GameManager {
                     .map(|b| b.with_info(BranchState::default()))
                     .collect_vec(),
                 branch_events: score.branch_events.clone(),
+                gogo_ranges: score.gogo_ranges.clone(),
             },
 
             auto: false,
@@ -256,6 +390,19 @@ impl GameManager {
             game_state_section: Default::default(),
             branch_event_pointer: 0,
             branch_event_branch_pointer: 0,
+            gogo_pointer: 0,
+            gogo_branch_pointer: 0,
+            double_hit_candidate: None,
+            judge_windows,
+            finish_time: score
+                .notes
+                .iter()
+                .map(|note| match &note.content {
+                    just::NoteContent::Single(..) => note.time + judge_windows.windows[Judge::Bad],
+                    just::NoteContent::Renda(renda) => renda.end_time,
+                })
+                .reduce(f64::max),
+            min_scroll_speed: score.min_scroll_speed(),
 
             game_state: Default::default(),
             animation_state: Default::default(),
@@ -276,6 +423,68 @@ impl GameManager {
         self.auto
     }
 
+    /// Returns whether every note has been passed at `time`.
+    /// A score without notes never finishes, so that its music can be played until the player
+    /// leaves.
+    pub fn finished(&self, time: f64) -> bool {
+        self.finish_time
+            .map_or(false, |finish_time| finish_time < time)
+    }
+
+    /// Returns the index in `score.notes` of the first note at or after `time`, among all
+    /// branches, or the number of notes if there is none.  The notes must be sorted by time,
+    /// as the parser does.
+    pub fn next_note_index(&self, time: f64) -> usize {
+        self.score.notes.partition_point(|note| note.time < time)
+    }
+
+    pub fn judge_windows(&self) -> &JudgeWindows {
+        &self.judge_windows
+    }
+
+    pub fn min_scroll_speed(&self) -> Bpm {
+        self.min_scroll_speed
+    }
+
+    /// Returns the branch being played at `time`, which is `Normal` until a branch is determined.
+    pub fn branch_at_time(&self, time: f64) -> BranchType {
+        // Branches are determined in order, so the determined ones that have been switched to
+        // form a prefix.
+        let branches = &self.score.branches;
+        let index = branches.partition_point(|branch| {
+            branch.switch_time <= time && branch.info.determined_branch.is_some()
+        });
+        index
+            .checked_sub(1)
+            .and_then(|i| branches[i].info.determined_branch)
+            .unwrap_or(BranchType::Normal)
+    }
+
+    /// Returns the hit count of the unlimited renda being played at `time`, if any.
+    pub fn active_renda_count(&self, time: f64) -> Option<u64> {
+        let branch = self.branch_at_time(time);
+        // Notes in a branch do not overlap, so only the last one started by `time` can be active.
+        let end = self.score.notes.partition_point(|note| note.time <= time);
+        self.score.notes[..end]
+            .iter()
+            .rev()
+            .find(|note| note.branch.map_or(true, |b| b == branch))
+            .and_then(|note| match &note.content {
+                NoteContent::Renda(RendaContent {
+                    kind: RendaKind::Unlimited(..),
+                    end_time,
+                    info,
+                }) if time <= *end_time => Some(info.count),
+                _ => None,
+            })
+    }
+
+    pub fn result(&self) -> GameResult {
+        GameResult {
+            game_state: self.game_state,
+        }
+    }
+
     pub fn hit(&mut self, color: Option<NoteColor>, time: f64) {
         // Process branch events (i.e. #LEVELHOLD and #SECTION)
         while let Some(event) = self.score.branch_events.get(self.branch_event_pointer) {
@@ -335,6 +544,17 @@ impl GameManager {
             }
         }
 
+        let branch = branch_at(&self.score.branches, &mut self.gogo_branch_pointer, time);
+        let gogo = gogo_at(
+            &self.score.gogo_ranges,
+            &mut self.gogo_pointer,
+            branch,
+            time,
+        );
+        if self.double_hit(color, time, gogo) {
+            return;
+        }
+
         let Self {
             game_state,
             animation_state,
@@ -345,40 +565,50 @@ impl GameManager {
             judge_bad_pointer,
             judge_branch_pointer,
             judge_branch_bad_pointer,
+            double_hit_candidate,
+            judge_windows,
             ..
         } = self;
+        let windows = judge_windows.windows;
 
         let check_note = |note: &mut Note, branch_matches: bool| match note.content {
             NoteContent::Single(ref mut single_note) => match note.time - time {
-                t if t.abs() <= OK_WINDOW => {
+                t if t.abs() <= windows[Judge::Ok] => {
                     if single_note.info.judge.is_none()
                         && single_note.corresponds(&color)
                         && branch_matches
                     {
-                        let judge = if t.abs() <= GOOD_WINDOW {
+                        let judge = if t.abs() <= windows[Judge::Good] {
                             Judge::Good
                         } else {
                             Judge::Ok
                         };
 
-                        game_state.update_with_judge(single_note, judge);
+                        game_state.update_with_judge(single_note, judge, gogo);
+                        let large = single_note.kind.size == NoteSize::Large;
+                        if large {
+                            single_note.info.first_hit_time = Some(time);
+                        }
                         animation_state.flying_notes.push_back(FlyingNote {
                             time,
                             kind: single_note.kind,
+                            double_hit: false,
+                        });
+                        animation_state.judge_strs.push_back(JudgeStr {
+                            time,
+                            judge,
+                            time_error: t,
                         });
-                        animation_state
-                            .judge_strs
-                            .push_back(JudgeStr { time, judge });
                         animation_state.last_combo_update = time;
 
-                        JudgeOnTimeline::BreakWith(())
+                        JudgeOnTimeline::BreakWith(large)
                     } else {
                         JudgeOnTimeline::Continue
                     }
                 }
                 t if t < 0.0 => {
                     if single_note.info.judge.is_none() && branch_matches {
-                        game_state.update_with_judge(single_note, JudgeOrPassed::Passed);
+                        game_state.update_with_judge(single_note, JudgeOrPassed::Passed, gogo);
                     }
                     JudgeOnTimeline::Past
                 }
@@ -398,6 +628,7 @@ impl GameManager {
                                         color,
                                         size: renda_u.size,
                                     },
+                                    double_hit: false,
                                 });
                             }
                             (RendaKind::Quota(ref mut renda_q), Some(NoteColor::Don)) => {
@@ -406,6 +637,12 @@ impl GameManager {
                                     renda.info.count += 1;
                                     if renda.info.count >= renda_q.quota {
                                         renda_q.info.finished = true;
+                                        game_state.balloon_burst_count += 1;
+                                        animation_state.balloon_bursts.push_back(BalloonBurst {
+                                            time,
+                                            kind: renda_q.kind,
+                                        });
+                                        animation_state.unplayed_burst_sound_count += 1;
                                     }
                                     animation_state.flying_notes.push_back(FlyingNote {
                                         time,
@@ -413,17 +650,26 @@ impl GameManager {
                                             color: NoteColor::Don,
                                             size: NoteSize::Small,
                                         },
+                                        double_hit: false,
                                     });
                                 }
                             }
                             _ => {}
                         };
-                        JudgeOnTimeline::BreakWith(())
+                        JudgeOnTimeline::BreakWith(false)
                     } else {
                         JudgeOnTimeline::Continue
                     }
                 }
-                _ if renda.end_time <= time => JudgeOnTimeline::Past,
+                _ if renda.end_time <= time => {
+                    if let RendaKind::Quota(ref mut renda_q) = renda.kind {
+                        if branch_matches && !renda_q.info.passed {
+                            renda_q.info.passed = true;
+                            game_state.balloon_count += 1;
+                        }
+                    }
+                    JudgeOnTimeline::Past
+                }
                 _ if time < note.time => match branch_matches {
                     true => JudgeOnTimeline::Break,
                     false => JudgeOnTimeline::Continue,
@@ -431,28 +677,33 @@ impl GameManager {
                 _ => unreachable!(),
             },
         };
-        let first_hit_check = check_note_wrapper(
+        let first_hit = check_note_wrapper(
             notes,
             branches,
             judge_pointer,
             judge_branch_pointer,
             check_note,
-        )
-        .is_some();
+        );
+        if let Some((index, true)) = first_hit {
+            *double_hit_candidate = Some((index, time));
+        }
+        let first_hit_check = first_hit.is_some();
 
         let check_note_bad = |note: &mut Note, branch_matches: bool| {
             if let NoteContent::Single(ref mut single_note) = note.content {
                 match note.time - time {
-                    t if t.abs() <= BAD_WINDOW => {
+                    t if t.abs() <= windows[Judge::Bad] => {
                         if matches!(single_note.info.judge, None | Some(JudgeOrPassed::Passed))
                             && single_note.corresponds(&color)
                             && branch_matches
                         {
                             let judge = Judge::Bad;
-                            game_state.update_with_judge(single_note, judge);
-                            animation_state
-                                .judge_strs
-                                .push_back(JudgeStr { time, judge });
+                            game_state.update_with_judge(single_note, judge, gogo);
+                            animation_state.judge_strs.push_back(JudgeStr {
+                                time,
+                                judge,
+                                time_error: t,
+                            });
                             JudgeOnTimeline::BreakWith(())
                         } else {
                             JudgeOnTimeline::Continue
@@ -477,6 +728,37 @@ impl GameManager {
         }
     }
 
+    /// Upgrade the large note hit just before to a double hit if `color` matches it.
+    /// Returns whether the hit has been consumed by the upgrade.
+    fn double_hit(&mut self, color: Option<NoteColor>, time: f64, gogo: bool) -> bool {
+        let (index, first_hit_time) = match self.double_hit_candidate {
+            Some((index, t)) if time - t <= DOUBLE_HIT_WINDOW => (index, t),
+            _ => {
+                self.double_hit_candidate = None;
+                return false;
+            }
+        };
+        // The judge pointer may have passed the note already if it was hit late
+        let note = match &mut self.score.notes[index].content {
+            NoteContent::Single(note) if note.corresponds(&color) => note,
+            _ => return false,
+        };
+        note.info.double_hit = true;
+        self.double_hit_candidate = None;
+        let judge = note.info.judge.map_or(Judge::Bad, Judge::from);
+        self.game_state.score += points(judge, gogo);
+        if let Some(flying_note) = self
+            .animation_state
+            .flying_notes
+            .iter_mut()
+            .rev()
+            .find(|note| note.time == first_hit_time)
+        {
+            flying_note.double_hit = true;
+        }
+        true
+    }
+
     pub fn flying_notes<F>(&mut self, filter_out: F) -> impl DoubleEndedIterator<Item = &FlyingNote>
     where
         F: FnMut(&&FlyingNote) -> bool,
@@ -484,6 +766,21 @@ impl GameManager {
         filter_out_and_iter(&mut self.animation_state.flying_notes, filter_out)
     }
 
+    pub fn balloon_bursts<F>(
+        &mut self,
+        filter_out: F,
+    ) -> impl DoubleEndedIterator<Item = &BalloonBurst>
+    where
+        F: FnMut(&&BalloonBurst) -> bool,
+    {
+        filter_out_and_iter(&mut self.animation_state.balloon_bursts, filter_out)
+    }
+
+    /// Returns how many burst sounds should be played since the last call.
+    pub fn take_burst_sound_count(&mut self) -> usize {
+        std::mem::take(&mut self.animation_state.unplayed_burst_sound_count)
+    }
+
     pub fn judge_strs<F>(&mut self, filter_out: F) -> impl DoubleEndedIterator<Item = &JudgeStr>
     where
         F: FnMut(&&JudgeStr) -> bool,
@@ -492,6 +789,26 @@ impl GameManager {
     }
 }
 
+/// Judge a whole play without SDL or audio, e.g. to verify a replay offline.
+/// `hits` are pairs of time and color sorted by time, where `None` only advances the time.
+/// The notes left unhit after the last one are judged as passed.
+pub fn grade<I>(score: &just::Score, judge_windows: JudgeWindows, hits: I) -> GameResult
+where
+    I: IntoIterator<Item = (f64, Option<NoteColor>)>,
+{
+    let mut game_manager = GameManager::with_judge_windows(score, judge_windows);
+    let mut last_time = f64::NEG_INFINITY;
+    for (time, color) in hits {
+        game_manager.hit(color, time);
+        last_time = time;
+    }
+    let end_time = game_manager
+        .finish_time
+        .map_or(last_time, |finish_time| finish_time.max(last_time));
+    game_manager.hit(None, end_time + 1.0);
+    game_manager.result()
+}
+
 fn branch_at(branches: &[Branch], branch_pointer: &mut usize, time: f64) -> BranchType {
     while branches.get(*branch_pointer).map_or(false, |branch| {
         branch.switch_time <= time && branch.info.determined_branch.is_some()
@@ -503,13 +820,34 @@ fn branch_at(branches: &[Branch], branch_pointer: &mut usize, time: f64) -> Bran
         .unwrap_or(BranchType::Normal)
 }
 
+/// Returns whether `time` is in a go-go time of the given branch.
+/// `gogo_pointer` skips the ranges that have ended, so `time` must not decrease between calls.
+fn gogo_at(
+    gogo_ranges: &[GogoRange],
+    gogo_pointer: &mut usize,
+    branch: BranchType,
+    time: f64,
+) -> bool {
+    while gogo_ranges
+        .get(*gogo_pointer)
+        .map_or(false, |range| range.end_time <= time)
+    {
+        *gogo_pointer += 1;
+    }
+    gogo_ranges[*gogo_pointer..]
+        .iter()
+        .take_while(|range| range.start_time <= time)
+        .any(|range| range.contains(time) && branch.matches(range.branch))
+}
+
+/// Returns the index of the note that `check_note` has broken with, together with its value.
 pub fn check_note_wrapper<F, T>(
     notes: &mut [Note],
     branches: &[Branch],
     judge_pointer: &mut usize,
     judge_branch_pointer: &mut usize,
     mut check_note: F,
-) -> Option<T>
+) -> Option<(usize, T)>
 where
     F: FnMut(&mut Note, bool) -> JudgeOnTimeline<T>,
 {
@@ -559,7 +897,7 @@ pub enum JudgeOnTimeline<T> {
     Break,
 }
 
-fn check_on_timeline<T, U, F>(vec: &mut [T], pointer: &mut usize, mut f: F) -> Option<U>
+fn check_on_timeline<T, U, F>(vec: &mut [T], pointer: &mut usize, mut f: F) -> Option<(usize, U)>
 where
     F: FnMut(&mut T) -> JudgeOnTimeline<U>,
 {
@@ -569,7 +907,7 @@ where
         match f(e) {
             JudgeOnTimeline::Past => *pointer = i + 1,
             JudgeOnTimeline::Break => break,
-            JudgeOnTimeline::BreakWith(u) => return Some(u),
+            JudgeOnTimeline::BreakWith(u) => return Some((i, u)),
             _ => {}
         }
     }
@@ -578,6 +916,361 @@ where
 
 #[cfg(test)]
 mod tests {
+    use super::{grade, GameManager, Judge, JudgeWindows, Timing};
+    use crate::score_builder::ScoreBuilder;
+    use crate::structs::{BranchCondition, BranchEventKind, BranchType, NoteColor, NoteSize};
+    use crate::tja::load_tja_from_str;
+
+    #[test]
+    fn test_wider_bad_window() {
+        let score = ScoreBuilder::new().don(1.0).build();
+        let judges_after_late_hit = |judge_windows| {
+            let mut game_manager = GameManager::with_judge_windows(&score, judge_windows);
+            game_manager.hit(Some(NoteColor::Don), 1.15);
+            game_manager
+                .judge_strs(|_| false)
+                .map(|j| j.judge)
+                .collect::<Vec<_>>()
+        };
+        // Too late to be judged, so the hit is ignored
+        assert!(judges_after_late_hit(JudgeWindows::default()).is_empty());
+        assert!(matches!(
+            judges_after_late_hit(JudgeWindows::default().scaled(1.5))[..],
+            [Judge::Bad]
+        ));
+    }
+
+    #[test]
+    fn test_judge_str_timing() {
+        let score = ScoreBuilder::new().don(1.0).don(2.0).don(3.0).build();
+        let mut game_manager = GameManager::new(&score);
+        game_manager.hit(Some(NoteColor::Don), 0.95);
+        game_manager.hit(Some(NoteColor::Don), 2.0);
+        game_manager.hit(Some(NoteColor::Don), 3.05);
+        let judge_strs = game_manager
+            .judge_strs(|_| false)
+            .map(|j| (j.judge, j.timing(&JudgeWindows::default())))
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            judge_strs[..],
+            [
+                (Judge::Ok, Some(Timing::Fast)),
+                (Judge::Good, None),
+                (Judge::Ok, Some(Timing::Slow)),
+            ]
+        ));
+
+        // The timing follows the windows in use
+        let judge_windows = JudgeWindows::default().scaled(2.0);
+        let mut game_manager = GameManager::with_judge_windows(&score, judge_windows);
+        game_manager.hit(Some(NoteColor::Don), 1.04);
+        let judge_strs = game_manager
+            .judge_strs(|_| false)
+            .map(|j| (j.judge, j.timing(&judge_windows)))
+            .collect::<Vec<_>>();
+        assert!(matches!(judge_strs[..], [(Judge::Good, None)]));
+    }
+
+    #[test]
+    fn test_result_after_finish() {
+        let score = ScoreBuilder::new()
+            .don(1.0)
+            .don(2.0)
+            .don(3.0)
+            .don(4.0)
+            .build();
+        let mut game_manager = GameManager::new(&score);
+        game_manager.hit(Some(NoteColor::Don), 1.0);
+        game_manager.hit(Some(NoteColor::Don), 2.0);
+        game_manager.hit(Some(NoteColor::Don), 3.1);
+        game_manager.hit(Some(NoteColor::Don), 4.0);
+        assert!(!game_manager.finished(4.0));
+        game_manager.hit(None, 5.0);
+        assert!(game_manager.finished(5.0));
+
+        let result = game_manager.result();
+        assert_eq!(result.game_state.good_count, 3);
+        assert_eq!(result.game_state.bad_count, 1);
+        assert_eq!(result.game_state.combo, 1);
+        assert_eq!(result.game_state.max_combo, 2);
+
+        // A score without notes never finishes
+        let score = ScoreBuilder::new().bar_line(0.0).build();
+        assert!(!GameManager::new(&score).finished(100.0));
+    }
+
+    #[test]
+    fn test_grade() {
+        let score = ScoreBuilder::new().don(1.0).don(2.0).don(3.0).build();
+        let perfect = grade(
+            &score,
+            JudgeWindows::default(),
+            vec![
+                (1.0, Some(NoteColor::Don)),
+                (2.0, Some(NoteColor::Don)),
+                (3.0, Some(NoteColor::Don)),
+            ],
+        );
+        assert_eq!(perfect.game_state.good_count, 3);
+        assert_eq!(perfect.game_state.score, 3000);
+        assert_eq!(perfect.game_state.max_combo, 3);
+
+        // An Ok, a note hit with the wrong color, which is not judged but passes as a miss, and a
+        // note left unhit
+        let imperfect = grade(
+            &score,
+            JudgeWindows::default(),
+            vec![(1.05, Some(NoteColor::Don)), (2.0, Some(NoteColor::Ka))],
+        );
+        assert_eq!(imperfect.game_state.good_count, 0);
+        assert_eq!(imperfect.game_state.ok_count, 1);
+        assert_eq!(imperfect.game_state.bad_count, 2);
+        assert_eq!(imperfect.game_state.score, 500);
+        assert_eq!(imperfect.game_state.combo, 0);
+    }
+
+    #[test]
+    fn test_gogo_bonus() {
+        let score = ScoreBuilder::new().don(1.0).don(2.0).gogo(1.5, 2.5).build();
+        let mut game_manager = GameManager::new(&score);
+        game_manager.hit(Some(NoteColor::Don), 1.0);
+        let outside = game_manager.game_state.score;
+        game_manager.hit(Some(NoteColor::Don), 2.0);
+        let inside = game_manager.game_state.score - outside;
+        assert_eq!(game_manager.game_state.good_count, 2);
+        assert_eq!((outside, inside), (1000, 1200));
+    }
+
+    #[test]
+    fn test_double_hit() {
+        let score = ScoreBuilder::new().large_don(1.0).large_don(2.0).build();
+        let mut game_manager = GameManager::new(&score);
+        game_manager.hit(Some(NoteColor::Don), 1.0);
+        assert_eq!(game_manager.game_state.score, 1000);
+        game_manager.hit(Some(NoteColor::Ka), 1.01);
+        game_manager.hit(Some(NoteColor::Don), 1.03);
+        assert_eq!(game_manager.game_state.score, 2000);
+        assert_eq!(game_manager.game_state.combo, 1);
+
+        // Too late for a double hit
+        game_manager.hit(Some(NoteColor::Don), 2.0);
+        game_manager.hit(Some(NoteColor::Don), 2.1);
+        assert_eq!(game_manager.game_state.score, 3000);
+
+        let double_hits = game_manager
+            .score
+            .notes
+            .iter()
+            .map(|note| match &note.content {
+                super::NoteContent::Single(note) => note.info.double_hit,
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(double_hits, vec![true, false]);
+        let flying_notes = game_manager
+            .flying_notes(|_| false)
+            .map(|note| note.double_hit)
+            .collect::<Vec<_>>();
+        assert_eq!(flying_notes, vec![true, false]);
+    }
+
+    #[test]
+    fn test_late_double_hit() {
+        let score = ScoreBuilder::new().large_don(1.0).build();
+        let mut game_manager = GameManager::new(&score);
+        game_manager.hit(Some(NoteColor::Don), 1.07);
+        assert_eq!(game_manager.game_state.score, 500);
+        // The tick of the next frame passes the note before the second hit arrives
+        game_manager.hit(None, 1.08);
+        game_manager.hit(Some(NoteColor::Don), 1.1);
+        assert_eq!(game_manager.game_state.score, 1000);
+        assert_eq!(game_manager.game_state.ok_count, 1);
+        assert_eq!(game_manager.game_state.bad_count, 0);
+    }
+
+    #[test]
+    fn test_next_note_index() {
+        let score = ScoreBuilder::new()
+            .don(1.0)
+            .ka(1.5)
+            .renda(2.0, 3.0, NoteSize::Small)
+            .don(4.0)
+            .build();
+        let game_manager = GameManager::new(&score);
+        assert_eq!(game_manager.next_note_index(0.0), 0);
+        assert_eq!(game_manager.next_note_index(1.0), 0);
+        assert_eq!(game_manager.next_note_index(1.2), 1);
+        // Inside the renda, the next note is the one after it
+        assert_eq!(game_manager.next_note_index(2.5), 3);
+        assert_eq!(game_manager.next_note_index(4.0), 3);
+        assert_eq!(game_manager.next_note_index(5.0), 4);
+    }
+
+    #[test]
+    fn test_branch_at_time() {
+        let score = ScoreBuilder::new()
+            .don(0.5)
+            .branch_point(1.0, 2.0, BranchCondition::Precision(50.0, 90.0))
+            .build();
+        let mut game_manager = GameManager::new(&score);
+        assert_eq!(game_manager.branch_at_time(2.5), BranchType::Normal);
+
+        game_manager.hit(Some(NoteColor::Don), 0.5);
+        game_manager.hit(None, 1.0);
+        assert_eq!(game_manager.branch_at_time(1.5), BranchType::Normal);
+        assert_eq!(game_manager.branch_at_time(2.5), BranchType::Master);
+        // Looking back does not disturb the judge
+        assert_eq!(game_manager.branch_at_time(0.0), BranchType::Normal);
+    }
+
+    #[test]
+    fn test_branch_by_score() {
+        let score = ScoreBuilder::new()
+            .don(0.5)
+            .don(1.0)
+            .don(1.5)
+            .don(2.0)
+            .branch_point(2.5, 3.0, BranchCondition::Score(2000, 2500))
+            .branch_event(0.75, BranchEventKind::Section)
+            .build();
+        let play = |hit_times: &[f64]| {
+            let mut game_manager = GameManager::new(&score);
+            for &time in hit_times {
+                game_manager.hit(Some(NoteColor::Don), time);
+            }
+            game_manager.hit(None, 2.5);
+            game_manager.branch_at_time(3.5)
+        };
+        // The points before #SECTION do not count
+        assert_eq!(play(&[0.5, 1.0, 1.5]), BranchType::Expert);
+        assert_eq!(play(&[0.5, 1.0, 1.5, 2.0]), BranchType::Master);
+        assert_eq!(play(&[0.5, 1.0]), BranchType::Normal);
+    }
+
+    #[test]
+    fn test_branch_judge_window() {
+        // One measure lasts one second; the branch is judged at 2.0 and switched at 3.0
+        let source = "BPM:240\n#START\n1111,\n#SECTION\n1111,\n1111,\n\
+            #BRANCHSTART p,50,90\n#N\n1,\n#E\n1,\n#M\n1,\n#BRANCHEND\n#END";
+        let score = load_tja_from_str(source.to_owned()).unwrap().score.unwrap();
+        assert_eq!(score.branches[0].judge_time, 2.0);
+        assert_eq!(score.branches[0].switch_time, 3.0);
+        let play = |hit_measures: &[usize]| {
+            let mut game_manager = GameManager::new(&score);
+            for i in 0..12 {
+                let time = i as f64 * 0.25;
+                if hit_measures.contains(&(i / 4)) {
+                    game_manager.hit(Some(NoteColor::Don), time);
+                } else {
+                    game_manager.hit(None, time + 0.2);
+                }
+            }
+            game_manager.hit(None, 3.5);
+            game_manager.branch_at_time(3.5)
+        };
+        // Only the measure between #SECTION and the judge time counts
+        assert_eq!(play(&[1]), BranchType::Master);
+        assert_eq!(play(&[0, 2]), BranchType::Normal);
+        assert_eq!(play(&[0, 1, 2]), BranchType::Master);
+    }
+
+    #[test]
+    fn test_level_hold() {
+        let condition = BranchCondition::Precision(50.0, 90.0);
+        let score = ScoreBuilder::new()
+            .don(0.2)
+            .don(0.6)
+            .don(0.8)
+            .don(3.0)
+            .don(4.0)
+            .branch_point(1.0, 2.0, condition)
+            .branch_point(2.5, 3.5, condition)
+            .branch_point(4.5, 5.5, condition)
+            .branch_event(0.5, BranchEventKind::Section)
+            .branch_event(2.2, BranchEventKind::LevelHold(BranchType::Expert))
+            .build();
+        let mut game_manager = GameManager::new(&score);
+        // The miss before #SECTION does not count, so the precision is 50%
+        game_manager.hit(None, 0.45);
+        game_manager.hit(Some(NoteColor::Don), 0.6);
+        game_manager.hit(None, 0.95);
+        game_manager.hit(None, 1.0);
+        assert_eq!(game_manager.branch_at_time(2.0), BranchType::Expert);
+
+        // Every note is missed after #LEVELHOLD, but the branch stays
+        for time in (22..60).map(|t| t as f64 / 10.0) {
+            game_manager.hit(None, time);
+        }
+        assert_eq!(game_manager.game_state.bad_count, 4);
+        assert_eq!(game_manager.branch_at_time(3.0), BranchType::Expert);
+        assert_eq!(game_manager.branch_at_time(4.0), BranchType::Expert);
+        assert_eq!(game_manager.branch_at_time(6.0), BranchType::Expert);
+    }
+
+    #[test]
+    fn test_active_renda_count() {
+        let score = ScoreBuilder::new()
+            .don(0.5)
+            .renda(1.0, 2.0, NoteSize::Small)
+            .build();
+        let mut game_manager = GameManager::new(&score);
+        assert_eq!(game_manager.active_renda_count(0.5), None);
+        assert_eq!(game_manager.active_renda_count(1.0), Some(0));
+        game_manager.hit(Some(NoteColor::Don), 1.1);
+        game_manager.hit(Some(NoteColor::Ka), 1.2);
+        assert_eq!(game_manager.active_renda_count(1.5), Some(2));
+        assert_eq!(game_manager.active_renda_count(2.5), None);
+    }
+
+    #[test]
+    fn test_balloon_burst_once() {
+        let score = ScoreBuilder::new().balloon(1.0, 3.0, 3).build();
+        let mut game_manager = GameManager::new(&score);
+        for &time in &[1.0, 1.1, 1.2] {
+            assert_eq!(game_manager.balloon_bursts(|_| false).count(), 0);
+            game_manager.hit(Some(NoteColor::Don), time);
+        }
+        for &time in &[1.3, 1.4] {
+            game_manager.hit(Some(NoteColor::Don), time);
+        }
+        let bursts = game_manager
+            .balloon_bursts(|_| false)
+            .map(|burst| burst.time)
+            .collect::<Vec<_>>();
+        assert_eq!(bursts, vec![1.2]);
+        assert_eq!(game_manager.take_burst_sound_count(), 1);
+        assert_eq!(game_manager.take_burst_sound_count(), 0);
+        assert_eq!(game_manager.game_state.renda_count, 3);
+    }
+
+    #[test]
+    fn test_balloon_counts() {
+        let score = ScoreBuilder::new()
+            .balloon(1.0, 2.0, 3)
+            .balloon(3.0, 4.0, 3)
+            .build();
+        let mut game_manager = GameManager::new(&score);
+        // The first one bursts, but the second one is hit only twice
+        for &time in &[1.0, 1.1, 1.2] {
+            game_manager.hit(Some(NoteColor::Don), time);
+        }
+        let state = game_manager.game_state;
+        assert_eq!((state.balloon_count, state.balloon_burst_count), (0, 1));
+
+        game_manager.hit(None, 2.5);
+        let state = game_manager.game_state;
+        assert_eq!((state.balloon_count, state.balloon_burst_count), (1, 1));
+
+        for &time in &[3.0, 3.1] {
+            game_manager.hit(Some(NoteColor::Don), time);
+        }
+        game_manager.hit(None, 5.0);
+        game_manager.hit(None, 6.0);
+        let state = game_manager.game_state;
+        assert_eq!((state.balloon_count, state.balloon_burst_count), (2, 1));
+        assert_eq!((state.combo, state.bad_count, state.renda_count), (0, 0, 5));
+    }
+
     /// In the original system, gauge count is calculated as integer with maximumm value of 10000.
     /// We use f64 to store the gauge value, which is precise enough to store exact values.
     #[test]
@@ -3,6 +3,7 @@ pub mod structs;
 
 pub mod assets;
 pub mod audio;
+pub mod calibration;
 pub mod config;
 pub mod errors;
 pub mod game;
@@ -10,6 +11,10 @@ pub mod game_graphics;
 pub mod game_manager;
 pub mod mode;
 pub mod pause;
+pub mod replay;
+pub mod result;
+pub mod score_builder;
+pub mod select;
 pub mod tja;
 pub mod utils;
 pub mod value_with_update_time;
@@ -1,19 +1,70 @@
+use std::path::{Path, PathBuf};
 use taiko_untitled::assets::Assets;
 use taiko_untitled::errors::{
     new_config_error, new_sdl_canvas_error, new_sdl_error, new_sdl_window_error, TaikoError,
     TaikoErrorCause,
 };
 use taiko_untitled::game::game;
+use taiko_untitled::game_graphics::fit_playfield_to_window;
 use taiko_untitled::mode::GameMode;
+use taiko_untitled::result::result;
+use taiko_untitled::select::{scan_songs, select, SelectBreak};
+use taiko_untitled::tja::load_tja_from_file;
+
+/// Exit code of `--validate` when the file has been parsed with warnings
+const EXIT_WARNINGS: i32 = 1;
+/// Exit code of `--validate` when the file could not be loaded at all
+const EXIT_LOAD_ERROR: i32 = 2;
+
+/// Parse `path` and print its warnings one per line as `path:line: message`.
+/// Returns the exit code, which is 0 only if there is a score without any warning.
+fn validate(path: &Path) -> i32 {
+    let song = match load_tja_from_file(path) {
+        Ok(song) => song,
+        Err(e) => {
+            println!("Failed to load {}", path.display());
+            println!("Caused by: {:?}", e);
+            return EXIT_LOAD_ERROR;
+        }
+    };
+    for warning in &song.warnings {
+        println!("{}:{}: {}", path.display(), warning.line, warning.message);
+    }
+    if song.score.is_none() {
+        println!("{}: No score is found", path.display());
+        return EXIT_WARNINGS;
+    }
+    if song.warnings.is_empty() {
+        0
+    } else {
+        EXIT_WARNINGS
+    }
+}
 
 fn main() -> Result<(), TaikoError> {
-    let config = taiko_untitled::config::get_config()
+    // `--validate <file.tja>` only checks the file, e.g. in the CI of a chart repository
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.get(1).map(String::as_str) == Some("--validate") {
+        let path = args.get(2).ok_or_else(|| TaikoError {
+            message: "Input file is not specified".to_owned(),
+            cause: TaikoErrorCause::None,
+        })?;
+        std::process::exit(validate(Path::new(path)));
+    }
+
+    let mut config = taiko_untitled::config::get_config()
         .map_err(|e| new_config_error("Failed to load configuration", e))?;
 
-    let tja_file_name = std::env::args().nth(1).ok_or_else(|| TaikoError {
-        message: "Input file is not specified".to_owned(),
-        cause: TaikoErrorCause::None,
-    })?;
+    let mut tja_file_name =
+        std::env::args()
+            .nth(1)
+            .map(PathBuf::from)
+            .ok_or_else(|| TaikoError {
+                message: "Input file is not specified".to_owned(),
+                cause: TaikoErrorCause::None,
+            })?;
+    // Given a directory, the songs in it are listed to choose from
+    let songs = tja_file_name.is_dir().then(|| scan_songs(&tja_file_name));
 
     let sdl_context =
         sdl2::init().map_err(|s| new_sdl_error("Failed to initialize SDL context", s))?;
@@ -23,6 +74,7 @@ fn main() -> Result<(), TaikoError> {
     let window = video_subsystem
         .window("", config.window.width, config.window.height)
         .allow_highdpi()
+        .resizable()
         .build()
         .map_err(|x| new_sdl_window_error("Failed to create main window", x))?;
 
@@ -40,18 +92,9 @@ fn main() -> Result<(), TaikoError> {
     let mut canvas = canvas
         .build()
         .map_err(|e| new_sdl_canvas_error("Failed to create SDL canvas", e))?;
-    match canvas.output_size() {
-        Ok((width, height)) => {
-            let scale = f32::min(width as f32 / 1920.0, height as f32 / 1080.0);
-            if let Err(s) = canvas.set_scale(scale, scale) {
-                eprintln!("Failed to scale the dimensions.  The drawing scale may not be valid.");
-                eprintln!("Caused by: {}", s);
-            }
-        }
-        Err(s) => {
-            eprintln!("Failed to get the canvas dimension.  The drawing scale may not be valid.");
-            eprintln!("Caused by: {}", s);
-        }
+    if let Err(e) = fit_playfield_to_window(&mut canvas) {
+        eprintln!("Failed to scale the playfield.  The drawing scale may not be valid.");
+        eprintln!("Caused by: {:?}", e);
     }
     let texture_creator = canvas.texture_creator();
 
@@ -59,34 +102,102 @@ fn main() -> Result<(), TaikoError> {
         .timer()
         .map_err(|s| new_sdl_error("Failed to initialize timer subsystem of SDL", s))?;
 
+    let joystick_subsystem = sdl_context
+        .joystick()
+        .map_err(|s| new_sdl_error("Failed to initialize joystick subsystem of SDL", s))?;
+
     let audio_manager = taiko_untitled::audio::AudioManager::new()?;
 
-    let mut assets = Assets::new(&texture_creator, &audio_manager)?;
+    let mut assets = Assets::new(
+        &texture_creator,
+        &audio_manager,
+        &config.assets_dir,
+        config.display.high_contrast_notes,
+    )?;
     {
         let volume = config.volume.se / 100.0;
         assets.chunks.sound_don.set_volume(volume);
         assets.chunks.sound_ka.set_volume(volume);
+        if let Some(sound_balloon) = &mut assets.chunks.sound_balloon {
+            sound_balloon.set_volume(volume);
+        }
         let volume = config.volume.song / 100.0;
         audio_manager.set_music_volume(volume)?;
+        audio_manager.set_clip_threshold(config.audio.clip_threshold)?;
     }
 
-    let mut mode = GameMode::Play;
+    let mut mode = match songs {
+        Some(..) => GameMode::Select,
+        None => GameMode::Play,
+    };
+    let mut selected = 0;
 
     loop {
         mode = match mode {
-            GameMode::Play => game(
+            GameMode::Select => match select(
                 &config,
                 &mut canvas,
+                &mut event_pump,
+                &assets,
+                songs.as_deref().unwrap_or_default(),
+                &mut selected,
+            )? {
+                SelectBreak::Play(path) => {
+                    tja_file_name = path;
+                    GameMode::Play
+                }
+                SelectBreak::Exit => GameMode::Exit,
+            },
+            GameMode::Play => game(
+                &mut config,
+                &mut canvas,
                 &event_subsystem,
                 &mut event_pump,
                 &mut timer_subsystem,
+                &joystick_subsystem,
                 &audio_manager,
                 &mut assets,
                 &tja_file_name,
             )?,
+            GameMode::Result(game_result) => {
+                match result(
+                    &config,
+                    &mut canvas,
+                    &mut event_pump,
+                    &mut assets,
+                    &game_result,
+                )? {
+                    GameMode::Play if songs.is_some() => GameMode::Select,
+                    mode => mode,
+                }
+            }
             GameMode::Exit => break,
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{validate, EXIT_LOAD_ERROR, EXIT_WARNINGS};
+
+    #[test]
+    fn test_validate_exit_code() {
+        let dir = std::env::temp_dir().join(format!("taiko-validate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let exit_code = |source: &str| {
+            let path = dir.join("song.tja");
+            std::fs::write(&path, source).unwrap();
+            validate(&path)
+        };
+        assert_eq!(exit_code("BALLOON:3\n#START\n1020,\n7008,\n#END"), 0);
+        assert_eq!(exit_code("#START\n#FOO\n1020,\n#END"), EXIT_WARNINGS);
+        assert_eq!(exit_code("#START\n10A0,\n#END"), EXIT_WARNINGS);
+        assert_eq!(exit_code("#START\n7008,\n#END"), EXIT_WARNINGS);
+        assert_eq!(exit_code("#START\n1020,\n"), EXIT_WARNINGS);
+        assert_eq!(exit_code("TITLE:No score\n"), EXIT_WARNINGS);
+        assert_eq!(validate(&dir.join("missing.tja")), EXIT_LOAD_ERROR);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,8 @@
+use crate::game_manager::GameResult;
+
 pub enum GameMode {
+    Select,
     Play,
+    Result(GameResult),
     Exit,
 }
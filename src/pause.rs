@@ -1,18 +1,24 @@
 use std::collections::BTreeSet;
 use std::sync::mpsc::Receiver;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use enum_map::enum_map;
+use enum_map::EnumMap;
 use itertools::iterate;
 use itertools::Itertools;
 use ordered_float::OrderedFloat;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
 use sdl2::render::WindowCanvas;
 use sdl2::EventPump;
 
 use crate::assets::Assets;
 use crate::audio::AudioManager;
 use crate::config::TaikoConfig;
+use crate::errors::new_sdl_error;
 use crate::errors::no_score_in_tja;
 use crate::errors::to_sdl_error;
 use crate::errors::TaikoError;
@@ -23,10 +29,15 @@ use crate::game_graphics::draw_bar_lines;
 use crate::game_graphics::draw_branch_overlay;
 use crate::game_graphics::draw_notes;
 use crate::game_graphics::game_rect;
+use crate::game_graphics::handle_window_event;
+use crate::game_graphics::visible_notes;
 use crate::game_graphics::BranchAnimationState;
 use crate::structs::just::Score;
+use crate::structs::Bpm;
 use crate::structs::BranchType;
 use crate::tja::Song;
+use crate::utils::FrameLimiter;
+use crate::value_with_update_time::Easing;
 use crate::value_with_update_time::EasingF64;
 use crate::value_with_update_time::EasingF64Impl;
 use crate::value_with_update_time::ValueWithUpdateTime;
@@ -35,6 +46,8 @@ struct PausedScore<'a> {
     score: &'a Score,
     measure_scroll_points: BTreeSet<OrderedFloat<f64>>,
     beat_scroll_points: BTreeSet<OrderedFloat<f64>>,
+    density: Option<NoteDensity>,
+    min_scroll_speed: Bpm,
 }
 
 impl<'a> PausedScore<'a> {
@@ -54,13 +67,172 @@ impl<'a> PausedScore<'a> {
             score,
             measure_scroll_points,
             beat_scroll_points,
+            density: NoteDensity::new(score),
+            min_scroll_speed: score.min_scroll_speed(),
         }
     }
+
+    /// Returns the scroll point, either a measure or a beat, nearest to `x`.
+    fn snap(&self, x: f64) -> f64 {
+        let x = OrderedFloat::from(x);
+        [&self.measure_scroll_points, &self.beat_scroll_points]
+            .iter()
+            .flat_map(|points| {
+                points
+                    .range(..=x)
+                    .next_back()
+                    .into_iter()
+                    .chain(points.range(x..).next())
+            })
+            .min_by_key(|p| OrderedFloat::from((**p - *x).abs()))
+            .map_or(*x, |p| **p)
+    }
+}
+
+/// The number of time buckets in the note density overview
+const DENSITY_BUCKET_COUNT: usize = 240;
+
+/// Where the note density overview is drawn, at the bottom of the screen
+fn density_rect() -> Rect {
+    Rect::new(0, 1000, 1920, 64)
+}
+
+/// The number of notes in each of the equal time buckets over the whole score, to give an
+/// overview of the score.
+struct NoteDensity {
+    start: f64,
+    end: f64,
+    counts: EnumMap<BranchType, Vec<usize>>,
+}
+
+impl NoteDensity {
+    /// Returns `None` if the score is empty or has no length.
+    fn new(score: &Score) -> Option<Self> {
+        let (start, end) = score.time_range().filter(|(start, end)| start < end)?;
+        let mut density = NoteDensity {
+            start,
+            end,
+            counts: enum_map! { _ => vec![0; DENSITY_BUCKET_COUNT] },
+        };
+        for note in &score.notes {
+            let bucket = density.bucket(note.time);
+            for (branch, counts) in &mut density.counts {
+                if branch.matches(note.branch) {
+                    counts[bucket] += 1;
+                }
+            }
+        }
+        Some(density)
+    }
+
+    fn bucket(&self, time: f64) -> usize {
+        let ratio = (time - self.start) / (self.end - self.start);
+        ((ratio * DENSITY_BUCKET_COUNT as f64).max(0.0) as usize).min(DENSITY_BUCKET_COUNT - 1)
+    }
+
+    /// Returns the time at `ratio` of the score, where 0 is the start and 1 is the end.
+    fn time_at(&self, ratio: f64) -> f64 {
+        self.start + (self.end - self.start) * ratio.max(0.0).min(1.0)
+    }
+
+    fn draw(
+        &self,
+        canvas: &mut WindowCanvas,
+        branch: BranchType,
+        music_position: f64,
+    ) -> Result<(), TaikoError> {
+        let rect = density_rect();
+        canvas.set_draw_color(Color::RGB(32, 32, 32));
+        canvas
+            .fill_rect(rect)
+            .map_err(|e| new_sdl_error("Failed to draw note density", e))?;
+
+        let counts = &self.counts[branch];
+        let max_count = counts.iter().copied().max().unwrap_or(0).max(1);
+        let width = rect.width() / DENSITY_BUCKET_COUNT as u32;
+        let bars = counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(i, &count)| {
+                let height = (rect.height() as usize * count / max_count) as u32;
+                Rect::new(
+                    rect.x() + (width * i as u32) as i32,
+                    rect.bottom() - height as i32,
+                    width,
+                    height,
+                )
+            })
+            .collect_vec();
+        canvas.set_draw_color(Color::RGB(200, 200, 200));
+        canvas
+            .fill_rects(&bars)
+            .map_err(|e| new_sdl_error("Failed to draw note density", e))?;
+
+        let ratio = (music_position - self.start) / (self.end - self.start);
+        let x = rect.x() + (rect.width() as f64 * ratio.max(0.0).min(1.0)) as i32;
+        canvas.set_draw_color(Color::RGB(0xf3, 0xff, 0x55));
+        canvas
+            .fill_rect(Rect::new(x - 1, rect.y(), 3, rect.height()))
+            .map_err(|e| new_sdl_error("Failed to draw note density", e))
+    }
+}
+
+/// Length of the music played after seeking, if `GameUserState::scrub_preview` is enabled
+const PREVIEW_DURATION: Duration = Duration::from_millis(200);
+/// The preview starts only after the position has stayed still for this duration,
+/// so that a quick series of seeks plays only one preview
+const PREVIEW_DEBOUNCE: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+struct ScrubPreview {
+    /// The position to preview and when it was requested
+    requested: Option<(f64, Instant)>,
+    /// When to stop the preview being played
+    stop_at: Option<Instant>,
+}
+
+impl ScrubPreview {
+    fn request(
+        &mut self,
+        audio_manager: &AudioManager<AutoEvent>,
+        time: f64,
+    ) -> Result<(), TaikoError> {
+        self.stop(audio_manager)?;
+        self.requested = Some((time, Instant::now()));
+        Ok(())
+    }
+
+    fn stop(&mut self, audio_manager: &AudioManager<AutoEvent>) -> Result<(), TaikoError> {
+        if self.stop_at.take().is_some() {
+            audio_manager.pause()?;
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, audio_manager: &AudioManager<AutoEvent>) -> Result<(), TaikoError> {
+        let now = Instant::now();
+        if let Some((time, requested_at)) = self.requested {
+            if now.duration_since(requested_at) >= PREVIEW_DEBOUNCE {
+                self.requested = None;
+                audio_manager.seek(time)?;
+                audio_manager.set_play_scheduled(false)?;
+                audio_manager.play()?;
+                self.stop_at = Some(now + PREVIEW_DURATION);
+            }
+        }
+        if self.stop_at.map_or(false, |stop_at| now >= stop_at) {
+            self.stop(audio_manager)?;
+        }
+        Ok(())
+    }
 }
 
 pub enum PauseBreak {
     Play(GameUserState),
+    Calibrate(GameUserState),
     Reload,
+    ReloadConfig,
     Exit,
 }
 
@@ -72,6 +244,7 @@ pub fn pause(
     audio_manager: &AudioManager<AutoEvent>,
     assets: &mut Assets,
     file_change_receiver: &Receiver<notify::DebouncedEvent>,
+    config_change_receiver: &Receiver<notify::DebouncedEvent>,
     song: &Song,
     mut game_user_state: GameUserState,
 ) -> Result<PauseBreak, TaikoError> {
@@ -80,30 +253,41 @@ pub fn pause(
 
     audio_manager.pause()?;
 
-    let mut music_position =
-        EasingF64Impl::new(game_user_state.time, Duration::from_millis(250), |x| {
-            1.0 - (1.0 - x).powi(3)
-        });
+    let mut music_position = EasingF64Impl::new(
+        game_user_state.time,
+        Duration::from_millis(250),
+        Easing::EaseOutCubic.into_fn(),
+    );
     let mut branch = ValueWithUpdateTime::new(BranchAnimationState::new(BranchType::Normal));
+    let mut frame_limiter = FrameLimiter::new(config.window.fps_cap());
+    let mut preview = ScrubPreview::default();
 
-    loop {
+    let res = loop {
         if let Some(res) = pause_loop(
             config,
             canvas,
             event_pump,
+            audio_manager,
             assets,
             &score,
             &mut music_position,
             &mut branch,
             &mut game_user_state,
+            &mut preview,
+            &mut frame_limiter,
         )? {
-            break Ok(res);
+            break res;
         }
 
         if file_change_receiver.try_iter().count() > 0 {
-            break Ok(PauseBreak::Reload);
+            break PauseBreak::Reload;
         }
-    }
+        if config_change_receiver.try_iter().count() > 0 {
+            break PauseBreak::ReloadConfig;
+        }
+    };
+    preview.stop(audio_manager)?;
+    Ok(res)
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -111,16 +295,21 @@ fn pause_loop<E>(
     config: &TaikoConfig,
     canvas: &mut WindowCanvas,
     event_pump: &mut EventPump,
+    audio_manager: &AudioManager<AutoEvent>,
     assets: &mut Assets,
     score: &PausedScore,
     music_position: &mut E,
     branch: &mut ValueWithUpdateTime<BranchAnimationState>,
     game_user_state: &mut GameUserState,
+    preview: &mut ScrubPreview,
+    frame_limiter: &mut FrameLimiter,
 ) -> Result<Option<PauseBreak>, TaikoError>
 where
     E: EasingF64,
 {
     for event in event_pump.poll_iter() {
+        handle_window_event(canvas, &event)?;
+        let position_before = music_position.get();
         match event {
             Event::Quit { .. } => return Ok(Some(PauseBreak::Exit)),
             Event::KeyDown {
@@ -134,7 +323,21 @@ where
                 Keycode::Q => {
                     return Ok(Some(PauseBreak::Reload));
                 }
+                Keycode::F5 => return Ok(Some(PauseBreak::Calibrate(*game_user_state))),
                 Keycode::F1 => game_user_state.auto = !game_user_state.auto,
+                Keycode::S => {
+                    game_user_state.scrub_preview = !game_user_state.scrub_preview;
+                    if !game_user_state.scrub_preview {
+                        preview.requested = None;
+                        preview.stop(audio_manager)?;
+                    }
+                }
+                Keycode::A => game_user_state.loop_start = Some(score.snap(music_position.get())),
+                Keycode::B => game_user_state.loop_end = Some(score.snap(music_position.get())),
+                Keycode::C => {
+                    game_user_state.loop_start = None;
+                    game_user_state.loop_end = None;
+                }
                 Keycode::PageDown => music_position.set_with(|x| {
                     score
                         .measure_scroll_points
@@ -174,9 +377,27 @@ where
                 }
                 _ => {}
             },
+            Event::MouseButtonDown {
+                mouse_btn: MouseButton::Left,
+                x,
+                y,
+                ..
+            } => {
+                let rect = density_rect();
+                if let Some(density) = &score.density {
+                    if rect.contains_point((x, y)) {
+                        let ratio = (x - rect.x()) as f64 / rect.width() as f64;
+                        music_position.set_with(|_| score.snap(density.time_at(ratio)));
+                    }
+                }
+            }
             _ => {}
         }
+        if game_user_state.scrub_preview && music_position.get() != position_before {
+            preview.request(audio_manager, music_position.get())?;
+        }
     }
+    preview.update(audio_manager)?;
 
     let display_position = music_position.get_eased();
 
@@ -198,20 +419,67 @@ where
             .filter(|x| branch.get().get().matches(x.branch));
         draw_bar_lines(canvas, display_position, bar_lines)?;
 
-        let notes = score
-            .score
-            .notes
+        let notes = visible_notes(&score.score.notes, display_position, score.min_scroll_speed)
             .iter()
             .rev()
             .filter(|x| branch.get().get().matches(x.branch));
-        draw_notes(canvas, assets, display_position, notes)?;
+        draw_notes(
+            canvas,
+            assets,
+            display_position,
+            notes,
+            config.display.scale_notes_at_edges,
+        )?;
     }
     canvas.set_clip_rect(None);
 
-    canvas.present();
-    if !config.window.vsync {
-        std::thread::sleep(Duration::from_secs_f64(1.0 / config.window.fps));
+    if let Some(density) = &score.density {
+        density.draw(canvas, branch.get().get(), display_position)?;
     }
 
+    canvas.present();
+    frame_limiter.wait();
+
     Ok(None)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{NoteDensity, DENSITY_BUCKET_COUNT};
+    use crate::score_builder::ScoreBuilder;
+    use crate::structs::{BranchType, NoteSize};
+
+    #[test]
+    fn test_note_density() {
+        let score = ScoreBuilder::new()
+            .don(0.0)
+            .don(0.01)
+            .ka(5.0)
+            .branch(Some(BranchType::Master))
+            .don(9.0)
+            .branch(None)
+            .renda(10.0, 12.0, NoteSize::Small)
+            .build();
+        let density = NoteDensity::new(&score).unwrap();
+        let nonzero = |branch| {
+            density.counts[branch]
+                .iter()
+                .enumerate()
+                .filter(|&(_, &count)| count > 0)
+                .map(|(i, &count)| (i, count))
+                .collect::<Vec<_>>()
+        };
+        let n = DENSITY_BUCKET_COUNT;
+        assert_eq!(
+            nonzero(BranchType::Normal),
+            vec![(0, 2), (n * 5 / 12, 1), (n * 10 / 12, 1)]
+        );
+        assert_eq!(
+            nonzero(BranchType::Master),
+            vec![(0, 2), (n * 5 / 12, 1), (n * 9 / 12, 1), (n * 10 / 12, 1)]
+        );
+        assert_eq!(density.time_at(0.5), 6.0);
+
+        assert!(NoteDensity::new(&ScoreBuilder::new().build()).is_none());
+    }
+}
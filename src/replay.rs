@@ -0,0 +1,129 @@
+use crate::errors::{new_replay_error, TaikoError};
+use crate::game_manager::GameManager;
+use crate::structs::NoteColor;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+/// The hits made in a play, including the ones by auto play, sorted by time.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Replay {
+    pub hits: Vec<ReplayHit>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ReplayHit {
+    pub time: f64,
+    pub color: NoteColor,
+}
+
+impl Replay {
+    pub fn record(&mut self, color: NoteColor, time: f64) {
+        self.hits.push(ReplayHit { time, color });
+    }
+
+    /// Forgets the hits at or after `time`, so that the play restarted from there replaces
+    /// them.
+    pub fn rewind(&mut self, time: f64) {
+        let len = self.hits.partition_point(|hit| hit.time < time);
+        self.hits.truncate(len);
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, TaikoError> {
+        let file =
+            File::open(path).map_err(|e| new_replay_error("Failed to open replay file", e))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| new_replay_error("Failed to parse replay file", e))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), TaikoError> {
+        let file =
+            File::create(path).map_err(|e| new_replay_error("Failed to create replay file", e))?;
+        serde_json::to_writer(BufWriter::new(file), self)
+            .map_err(|e| new_replay_error("Failed to write replay file", e))
+    }
+}
+
+/// Feeds the recorded hits to a `GameManager` as the music goes on.
+pub struct ReplayPlayer<'a> {
+    hits: &'a [ReplayHit],
+    pointer: usize,
+}
+
+impl<'a> ReplayPlayer<'a> {
+    pub fn new(replay: &'a Replay) -> Self {
+        Self {
+            hits: &replay.hits,
+            pointer: 0,
+        }
+    }
+
+    /// Skips the hits before `time`, so that the play can be started from the middle.
+    pub fn seek(&mut self, time: f64) {
+        self.pointer = self.hits.partition_point(|hit| hit.time < time);
+    }
+
+    /// Feeds all the hits until `time` that have not been fed yet, and then advances the game
+    /// to `time`.
+    pub fn feed(&mut self, game_manager: &mut GameManager, time: f64) {
+        while let Some(hit) = self.hits.get(self.pointer).filter(|hit| hit.time <= time) {
+            game_manager.hit(Some(hit.color), hit.time);
+            self.pointer += 1;
+        }
+        game_manager.hit(None, time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Replay, ReplayPlayer};
+    use crate::game_manager::GameManager;
+    use crate::score_builder::ScoreBuilder;
+    use crate::structs::NoteColor;
+
+    #[test]
+    fn test_replay_reproduces_game_state() {
+        let score = ScoreBuilder::new()
+            .don(1.0)
+            .ka(1.5)
+            .don(2.0)
+            .ka(2.5)
+            .build();
+        let mut recorded = GameManager::new(&score);
+        let mut recording = Replay::default();
+        for &(color, time) in &[
+            (Some(NoteColor::Don), 1.01),
+            (None, 1.2),
+            (Some(NoteColor::Ka), 1.55),
+            (Some(NoteColor::Ka), 2.0),
+            (None, 3.0),
+        ] {
+            recorded.hit(color, time);
+            if let Some(color) = color {
+                recording.record(color, time);
+            }
+        }
+        assert_eq!(recording.hits.len(), 3);
+
+        let json = serde_json::to_string(&recording).unwrap();
+        let replay = serde_json::from_str::<Replay>(&json).unwrap();
+        let mut replayed = GameManager::new(&score);
+        ReplayPlayer::new(&replay).feed(&mut replayed, 3.0);
+
+        assert_eq!(recorded.game_state, replayed.game_state);
+        assert_eq!(replayed.game_state.good_count, 1);
+        assert_eq!(replayed.game_state.ok_count, 1);
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut replay = Replay::default();
+        for &time in &[1.0, 2.0, 3.0] {
+            replay.record(NoteColor::Don, time);
+        }
+        replay.rewind(2.0);
+        let times = replay.hits.iter().map(|hit| hit.time).collect::<Vec<_>>();
+        assert_eq!(times, vec![1.0]);
+    }
+}
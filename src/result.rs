@@ -0,0 +1,54 @@
+use crate::assets::Assets;
+use crate::config::TaikoConfig;
+use crate::errors::TaikoError;
+use crate::game_graphics::{draw_result, handle_window_event};
+use crate::game_manager::GameResult;
+use crate::mode::GameMode;
+use crate::utils::FrameLimiter;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::render::WindowCanvas;
+use sdl2::EventPump;
+use std::time::{Duration, Instant};
+
+/// Inputs right after the song finishes are ignored, so that the last hits do not skip the result.
+const INPUT_DELAY: Duration = Duration::from_secs(1);
+
+pub fn result(
+    config: &TaikoConfig,
+    canvas: &mut WindowCanvas,
+    event_pump: &mut EventPump,
+    assets: &mut Assets,
+    game_result: &GameResult,
+) -> Result<GameMode, TaikoError> {
+    let shown_at = Instant::now();
+    let mut frame_limiter = FrameLimiter::new(config.window.fps_cap());
+    loop {
+        for event in event_pump.poll_iter() {
+            handle_window_event(canvas, &event)?;
+            match event {
+                Event::Quit { .. } => return Ok(GameMode::Exit),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    ..
+                } => {}
+                Event::KeyDown { repeat: false, .. } | Event::JoyButtonDown { .. }
+                    if shown_at.elapsed() >= INPUT_DELAY =>
+                {
+                    return Ok(GameMode::Play)
+                }
+                _ => {}
+            }
+        }
+
+        draw_result(
+            canvas,
+            assets,
+            game_result,
+            shown_at.elapsed().as_secs_f64(),
+        )?;
+
+        canvas.present();
+        frame_limiter.wait();
+    }
+}
@@ -0,0 +1,172 @@
+use crate::structs::just::{
+    Branch, Note, NoteContent, QuotaRenda, RendaContent, RendaKind, Score, SingleNote,
+    UnlimitedRenda,
+};
+use crate::structs::{
+    BarLine, BarLineKind, Bpm, BranchCondition, BranchEvent, BranchEventKind, BranchType,
+    GogoRange, NoteColor, NoteSize, QuotaRendaKind, SingleNoteKind,
+};
+
+/// Builds a `just::Score` note by note, mainly to write tests concisely.
+/// The notes and bar lines are added in the order of the calls, at 120 BPM in the common
+/// branch unless `scroll_speed` or `branch` says otherwise.
+///
+/// ```
+/// use taiko_untitled::game_manager::GameManager;
+/// use taiko_untitled::score_builder::ScoreBuilder;
+/// use taiko_untitled::structs::NoteColor;
+///
+/// let score = ScoreBuilder::new()
+///     .bar_line(0.0)
+///     .don(1.0)
+///     .ka(1.5)
+///     .balloon(2.0, 3.0, 5)
+///     .build();
+/// let mut game_manager = GameManager::new(&score);
+/// game_manager.hit(Some(NoteColor::Don), 1.0);
+/// game_manager.hit(None, 2.0);
+/// assert_eq!(game_manager.game_state.good_count, 1);
+/// assert_eq!(game_manager.game_state.bad_count, 1);
+/// ```
+pub struct ScoreBuilder {
+    score: Score,
+    scroll_speed: Bpm,
+    branch: Option<BranchType>,
+}
+
+impl Default for ScoreBuilder {
+    fn default() -> Self {
+        ScoreBuilder {
+            score: Score::default(),
+            scroll_speed: Bpm(120.0),
+            branch: None,
+        }
+    }
+}
+
+impl ScoreBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the scroll speed of the notes and bar lines added after this call.
+    pub fn scroll_speed(mut self, scroll_speed: f64) -> Self {
+        self.scroll_speed = Bpm(scroll_speed);
+        self
+    }
+
+    /// Put the notes, bar lines and go-go times added after this call in `branch`, or in every
+    /// branch if `None`.
+    pub fn branch(mut self, branch: Option<BranchType>) -> Self {
+        self.branch = branch;
+        self
+    }
+
+    pub fn don(self, time: f64) -> Self {
+        self.note(time, NoteColor::Don, NoteSize::Small)
+    }
+
+    pub fn ka(self, time: f64) -> Self {
+        self.note(time, NoteColor::Ka, NoteSize::Small)
+    }
+
+    pub fn large_don(self, time: f64) -> Self {
+        self.note(time, NoteColor::Don, NoteSize::Large)
+    }
+
+    pub fn large_ka(self, time: f64) -> Self {
+        self.note(time, NoteColor::Ka, NoteSize::Large)
+    }
+
+    pub fn note(self, time: f64, color: NoteColor, size: NoteSize) -> Self {
+        let kind = SingleNoteKind { color, size };
+        self.push_note(time, NoteContent::Single(SingleNote { kind, info: () }))
+    }
+
+    /// Add an unlimited renda from `time` to `end_time`.
+    pub fn renda(self, time: f64, end_time: f64, size: NoteSize) -> Self {
+        let kind = RendaKind::Unlimited(UnlimitedRenda { size, info: () });
+        self.push_renda(time, end_time, kind)
+    }
+
+    pub fn balloon(self, time: f64, end_time: f64, quota: u64) -> Self {
+        self.quota_renda(time, end_time, quota, QuotaRendaKind::Balloon)
+    }
+
+    pub fn quota_renda(self, time: f64, end_time: f64, quota: u64, kind: QuotaRendaKind) -> Self {
+        let kind = RendaKind::Quota(QuotaRenda {
+            kind,
+            quota,
+            info: (),
+        });
+        self.push_renda(time, end_time, kind)
+    }
+
+    pub fn bar_line(mut self, time: f64) -> Self {
+        self.score.bar_lines.push(BarLine {
+            time,
+            scroll_speed: self.scroll_speed,
+            kind: BarLineKind::Normal,
+            visible: true,
+            branch: self.branch,
+        });
+        self
+    }
+
+    /// Add a branch judged at `judge_time` and switched at `switch_time`.
+    pub fn branch_point(
+        mut self,
+        judge_time: f64,
+        switch_time: f64,
+        condition: BranchCondition,
+    ) -> Self {
+        self.score.branches.push(Branch {
+            judge_time,
+            switch_time,
+            scroll_speed: self.scroll_speed,
+            condition,
+            info: (),
+        });
+        self
+    }
+
+    /// Add a go-go time from `start_time` to `end_time`.
+    pub fn gogo(mut self, start_time: f64, end_time: f64) -> Self {
+        self.score.gogo_ranges.push(GogoRange {
+            start_time,
+            end_time,
+            branch: self.branch,
+        });
+        self
+    }
+
+    /// Add a `#SECTION` or `#LEVELHOLD` at `time`.
+    pub fn branch_event(mut self, time: f64, kind: BranchEventKind) -> Self {
+        self.score.branch_events.push(BranchEvent { time, kind });
+        self
+    }
+
+    pub fn build(self) -> Score {
+        self.score
+    }
+
+    fn push_renda(self, time: f64, end_time: f64, kind: RendaKind) -> Self {
+        let content = NoteContent::Renda(RendaContent {
+            kind,
+            end_time,
+            info: (),
+        });
+        self.push_note(time, content)
+    }
+
+    fn push_note(mut self, time: f64, content: NoteContent) -> Self {
+        self.score.notes.push(Note {
+            scroll_speed: self.scroll_speed,
+            time,
+            content,
+            branch: self.branch,
+            info: (),
+        });
+        self
+    }
+}
@@ -0,0 +1,169 @@
+use crate::assets::Assets;
+use crate::config::TaikoConfig;
+use crate::errors::{new_sdl_error, TaikoError};
+use crate::game_graphics::{draw_song_list, handle_window_event};
+use crate::tja::load_tja_header;
+use crate::utils::FrameLimiter;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::render::WindowCanvas;
+use sdl2::EventPump;
+use std::path::{Path, PathBuf};
+
+pub struct SongEntry {
+    pub path: PathBuf,
+    /// `TITLE` of the song, or the file name if absent
+    pub title: String,
+    pub level: Option<u32>,
+}
+
+pub enum SelectBreak {
+    Play(PathBuf),
+    Exit,
+}
+
+/// Find the tja files in `dir` and its subdirectories, sorted by path.
+/// Only the metadata of each file is loaded; the files that cannot be loaded are skipped.
+pub fn scan_songs<P: AsRef<Path>>(dir: P) -> Vec<SongEntry> {
+    let mut paths = Vec::new();
+    collect_tja_paths(dir.as_ref(), &mut paths);
+    paths.sort();
+    paths
+        .into_iter()
+        .filter_map(|path| match load_tja_header(&path) {
+            Ok(header) => {
+                let title = header.title.unwrap_or_else(|| {
+                    path.file_stem()
+                        .map_or_else(String::new, |s| s.to_string_lossy().into_owned())
+                });
+                Some(SongEntry {
+                    path,
+                    title,
+                    level: header.level,
+                })
+            }
+            Err(e) => {
+                println!("Failed to load {:?}", path);
+                println!("Caused by: {:?}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+fn collect_tja_paths(dir: &Path, paths: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            println!("Failed to read directory {:?}", dir);
+            println!("Caused by: {:?}", e);
+            return;
+        }
+    };
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        if path.is_dir() {
+            collect_tja_paths(&path, paths);
+        } else if path
+            .extension()
+            .map_or(false, |ext| ext.eq_ignore_ascii_case("tja"))
+        {
+            paths.push(path);
+        }
+    }
+}
+
+/// Let the player choose a song with the arrow keys.  There is no font to draw the titles with,
+/// so the title of the selected song is shown in the window title.
+pub fn select(
+    config: &TaikoConfig,
+    canvas: &mut WindowCanvas,
+    event_pump: &mut EventPump,
+    assets: &Assets,
+    songs: &[SongEntry],
+    selected: &mut usize,
+) -> Result<SelectBreak, TaikoError> {
+    if songs.is_empty() {
+        println!("No tja file was found");
+        return Ok(SelectBreak::Exit);
+    }
+    *selected = (*selected).min(songs.len() - 1);
+    let mut frame_limiter = FrameLimiter::new(config.window.fps_cap());
+    let mut shown_title = None;
+    loop {
+        for event in event_pump.poll_iter() {
+            handle_window_event(canvas, &event)?;
+            match event {
+                Event::Quit { .. } => return Ok(SelectBreak::Exit),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => match keycode {
+                    Keycode::Up => *selected = (*selected + songs.len() - 1) % songs.len(),
+                    Keycode::Down => *selected = (*selected + 1) % songs.len(),
+                    Keycode::Return | Keycode::Space => {
+                        return Ok(SelectBreak::Play(songs[*selected].path.clone()))
+                    }
+                    Keycode::Escape => return Ok(SelectBreak::Exit),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+
+        if shown_title != Some(*selected) {
+            let song = &songs[*selected];
+            let title = match song.level {
+                Some(level) => format!("{} \u{2605}{}", song.title, level),
+                None => song.title.clone(),
+            };
+            canvas
+                .window_mut()
+                .set_title(&title)
+                .map_err(|e| new_sdl_error("Failed to set the window title", e.to_string()))?;
+            shown_title = Some(*selected);
+        }
+        draw_song_list(canvas, assets, songs.len(), *selected)?;
+
+        canvas.present();
+        frame_limiter.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collect_tja_paths, scan_songs};
+
+    #[test]
+    fn test_scan_songs() {
+        let dir = std::env::temp_dir().join(format!("taiko-scan-songs-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("b.tja"), "TITLE:Beta\nLEVEL:8\n#START\n1,\n#END\n").unwrap();
+        std::fs::write(dir.join("sub").join("a.TJA"), "#START\n1,\n#END\n").unwrap();
+        std::fs::write(dir.join("readme.txt"), "TITLE:Not a song\n").unwrap();
+
+        let mut paths = Vec::new();
+        collect_tja_paths(&dir, &mut paths);
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![dir.join("b.tja"), dir.join("sub").join("a.TJA")]
+        );
+
+        let songs = scan_songs(&dir);
+        let songs = songs
+            .iter()
+            .map(|song| (song.title.as_str(), song.level))
+            .collect::<Vec<_>>();
+        assert_eq!(songs, vec![("Beta", Some(8)), ("a", None)]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_scan_missing_directory() {
+        let dir = std::env::temp_dir().join(format!("taiko-no-songs-{}", std::process::id()));
+        assert!(scan_songs(dir).is_empty());
+    }
+}
@@ -1,16 +1,23 @@
 use enum_map::Enum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+use std::time::SystemTime;
 
 pub mod typed {
     use super::*;
+    use std::cmp::Ordering;
     use std::fmt::Debug;
 
     pub trait AdditionalInfo {
-        type Note: Debug + Clone;
-        type SingleNote: Debug + Clone;
-        type RendaContent: Debug + Clone;
-        type UnlimitedRenda: Debug + Clone;
-        type QuotaRenda: Debug + Clone;
-        type Branch: Debug + Clone;
+        type Note: Debug + Clone + PartialEq;
+        type SingleNote: Debug + Clone + PartialEq;
+        type RendaContent: Debug + Clone + PartialEq;
+        type UnlimitedRenda: Debug + Clone + PartialEq;
+        type QuotaRenda: Debug + Clone + PartialEq;
+        type Branch: Debug + Clone + PartialEq;
     }
 
     impl AdditionalInfo for () {
@@ -22,15 +29,165 @@ pub mod typed {
         type Branch = ();
     }
 
-    #[derive(Default, Debug)]
+    #[derive(Default, Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(bound(
+        serialize = "Note<T>: Serialize, Branch<T>: Serialize",
+        deserialize = "Note<T>: Deserialize<'de>, Branch<T>: Deserialize<'de>"
+    ))]
     pub struct Score<T: AdditionalInfo> {
         pub notes: Vec<Note<T>>,
         pub bar_lines: Vec<BarLine>,
         pub branches: Vec<Branch<T>>,
         pub branch_events: Vec<BranchEvent>,
+        pub gogo_ranges: Vec<GogoRange>,
     }
 
-    #[derive(Clone, Debug)]
+    impl<T: AdditionalInfo> Score<T> {
+        /// Returns the 0-based index of the measure that contains `time` in the given branch,
+        /// or `None` if `time` is before the first bar line.
+        pub fn measure_index(&self, time: f64, branch: BranchType) -> Option<usize> {
+            // Bar lines are sorted by time once restricted to a single branch
+            self.bar_lines
+                .iter()
+                .filter(|b| branch.matches(b.branch))
+                .take_while(|b| b.time <= time)
+                .count()
+                .checked_sub(1)
+        }
+
+        /// Returns the go-go time that contains `time` in the given branch, if any.
+        pub fn gogo_range_at(&self, time: f64, branch: BranchType) -> Option<&GogoRange> {
+            self.gogo_ranges
+                .iter()
+                .find(|r| r.contains(time) && branch.matches(r.branch))
+        }
+
+        /// Returns the earliest and the latest time of the notes, including the end of rendas,
+        /// and the bar lines, or `None` if there are neither.
+        pub fn time_range(&self) -> Option<(f64, f64)> {
+            let note_ranges = self.notes.iter().map(|note| match &note.content {
+                NoteContent::Single(..) => (note.time, note.time),
+                NoteContent::Renda(renda) => (note.time, renda.end_time),
+            });
+            let bar_line_ranges = self.bar_lines.iter().map(|b| (b.time, b.time));
+            note_ranges
+                .chain(bar_line_ranges)
+                .fold(None, |range, (s, e)| match range {
+                    None => Some((s, e)),
+                    Some((start, end)) => Some((f64::min(start, s), f64::max(end, e))),
+                })
+        }
+
+        /// Returns the smallest absolute scroll speed of the notes, or infinity if there are none.
+        pub fn min_scroll_speed(&self) -> Bpm {
+            let speed = self.notes.iter().map(|note| note.scroll_speed.0.abs());
+            Bpm(speed.fold(f64::INFINITY, f64::min))
+        }
+
+        /// Returns the note that comes first, among all branches.
+        pub fn first_note(&self) -> Option<&Note<T>> {
+            self.notes
+                .iter()
+                .min_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(Ordering::Equal))
+        }
+
+        /// Returns the note that comes last, among all branches.
+        /// A renda is compared by its start, not its end.
+        pub fn last_note(&self) -> Option<&Note<T>> {
+            self.notes
+                .iter()
+                .max_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(Ordering::Equal))
+        }
+
+        /// Returns the notes that are off the usual subdivisions of their measure, that is, whose
+        /// position in the measure is farther than `tolerance` seconds from any multiple of
+        /// 1/192, 1/128 or 1/144 of it.  The tempo is assumed to be constant within each measure,
+        /// and the notes after the last bar line are not examined.
+        pub fn offbeat_notes(&self, tolerance: f64) -> Vec<&Note<T>> {
+            self.notes
+                .iter()
+                .filter(|note| {
+                    let branch = note.branch.unwrap_or_default();
+                    let bar_lines = || self.bar_lines.iter().filter(|b| branch.matches(b.branch));
+                    let start = bar_lines().filter(|b| b.time <= note.time).last();
+                    let end = bar_lines().find(|b| b.time > note.time);
+                    let (start, end) = match (start, end) {
+                        (Some(start), Some(end)) => (start.time, end.time),
+                        _ => return false,
+                    };
+                    let duration = end - start;
+                    let position = (note.time - start) / duration;
+                    [192.0, 128.0, 144.0].iter().all(|&division: &f64| {
+                        let steps = position * division;
+                        (steps - steps.round()).abs() * duration / division > tolerance
+                    })
+                })
+                .collect()
+        }
+
+        /// Returns the branches, the `#SECTION`s, the `#LEVELHOLD`s and the start and the end of
+        /// go-go times in the order of time.  A branch is placed at its switch time.
+        /// The events at the same time keep the order above.
+        pub fn events(&self) -> impl Iterator<Item = ScoreEvent<T>> {
+            let branches = self.branches.iter().map(|branch| ScoreEvent {
+                time: branch.switch_time,
+                kind: ScoreEventKind::Branch(branch),
+            });
+            let branch_events = self.branch_events.iter().map(|event| ScoreEvent {
+                time: event.time,
+                kind: ScoreEventKind::BranchEvent(event.kind),
+            });
+            let gogo_times = self.gogo_ranges.iter().flat_map(|range| {
+                let start = ScoreEvent {
+                    time: range.start_time,
+                    kind: ScoreEventKind::GogoStart(range),
+                };
+                let end = ScoreEvent {
+                    time: range.end_time,
+                    kind: ScoreEventKind::GogoEnd(range),
+                };
+                std::iter::once(start).chain(std::iter::once(end))
+            });
+            let mut events = branches
+                .chain(branch_events)
+                .chain(gogo_times)
+                .collect::<Vec<_>>();
+            events.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(Ordering::Equal));
+            events.into_iter()
+        }
+
+        /// Clone this score without the additional information.
+        pub fn to_just(&self) -> Score<()> {
+            Score {
+                notes: self.notes.iter().map(Note::to_just).collect(),
+                bar_lines: self.bar_lines.clone(),
+                branches: self.branches.iter().map(|b| b.with_info(())).collect(),
+                branch_events: self.branch_events.clone(),
+                gogo_ranges: self.gogo_ranges.clone(),
+            }
+        }
+    }
+
+    /// An event of a score at `time`, returned by `Score::events`
+    #[derive(Debug)]
+    pub struct ScoreEvent<'a, T: AdditionalInfo> {
+        pub time: f64,
+        pub kind: ScoreEventKind<'a, T>,
+    }
+
+    #[derive(Debug)]
+    pub enum ScoreEventKind<'a, T: AdditionalInfo> {
+        Branch(&'a Branch<T>),
+        BranchEvent(BranchEventKind),
+        GogoStart(&'a GogoRange),
+        GogoEnd(&'a GogoRange),
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(bound(
+        serialize = "T::Note: Serialize, NoteContent<T>: Serialize",
+        deserialize = "T::Note: Deserialize<'de>, NoteContent<T>: Deserialize<'de>"
+    ))]
     pub struct Note<T: AdditionalInfo> {
         pub scroll_speed: Bpm,
         pub time: f64,
@@ -39,22 +196,45 @@ pub mod typed {
         pub info: T::Note,
     }
 
-    // impl <T: AdditionalInfo> Note<T> {
-    //     fn cloned_with<U, F>(self, f: F) -> Note<U> where U: AdditionalInfo, F: FnOnce() -> U::Note {
-    //         Note {
-    //             scroll_speed: self.scroll_speed,
-    //             time: self.time,
-    //         }
-    //     }
-    // }
+    impl<T: AdditionalInfo> Note<T> {
+        /// Clone this note without the additional information.
+        pub fn to_just(&self) -> Note<()> {
+            Note {
+                scroll_speed: self.scroll_speed,
+                time: self.time,
+                content: self.content.to_just(),
+                branch: self.branch,
+                info: (),
+            }
+        }
+    }
 
-    #[derive(Clone, Debug)]
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(bound(
+        serialize = "SingleNote<T>: Serialize, RendaContent<T>: Serialize",
+        deserialize = "SingleNote<T>: Deserialize<'de>, RendaContent<T>: Deserialize<'de>"
+    ))]
     pub enum NoteContent<T: AdditionalInfo> {
         Single(SingleNote<T>),
         Renda(RendaContent<T>),
     }
 
-    #[derive(Clone, Copy, Debug)]
+    impl<T: AdditionalInfo> NoteContent<T> {
+        pub fn to_just(&self) -> NoteContent<()> {
+            match self {
+                NoteContent::Single(single_note) => {
+                    NoteContent::Single(single_note.clone_with_default())
+                }
+                NoteContent::Renda(renda) => NoteContent::Renda(renda.to_just()),
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(bound(
+        serialize = "T::SingleNote: Serialize",
+        deserialize = "T::SingleNote: Deserialize<'de>"
+    ))]
     pub struct SingleNote<T: AdditionalInfo> {
         pub kind: SingleNoteKind,
         pub info: T::SingleNote,
@@ -81,20 +261,46 @@ pub mod typed {
         }
     }
 
-    #[derive(Clone, Debug)]
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(bound(
+        serialize = "RendaKind<T>: Serialize, T::RendaContent: Serialize",
+        deserialize = "RendaKind<T>: Deserialize<'de>, T::RendaContent: Deserialize<'de>"
+    ))]
     pub struct RendaContent<T: AdditionalInfo> {
         pub kind: RendaKind<T>,
         pub end_time: f64,
         pub info: T::RendaContent,
     }
 
-    #[derive(Clone, Debug)]
+    impl<T: AdditionalInfo> RendaContent<T> {
+        pub fn to_just(&self) -> RendaContent<()> {
+            let kind = match &self.kind {
+                RendaKind::Unlimited(renda) => RendaKind::Unlimited(renda.clone_with_default()),
+                RendaKind::Quota(renda) => RendaKind::Quota(renda.clone_with_default()),
+            };
+            RendaContent {
+                kind,
+                end_time: self.end_time,
+                info: (),
+            }
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(bound(
+        serialize = "UnlimitedRenda<T>: Serialize, QuotaRenda<T>: Serialize",
+        deserialize = "UnlimitedRenda<T>: Deserialize<'de>, QuotaRenda<T>: Deserialize<'de>"
+    ))]
     pub enum RendaKind<T: AdditionalInfo> {
         Unlimited(UnlimitedRenda<T>),
         Quota(QuotaRenda<T>),
     }
 
-    #[derive(Clone, Copy, Debug)]
+    #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(bound(
+        serialize = "T::UnlimitedRenda: Serialize",
+        deserialize = "T::UnlimitedRenda: Deserialize<'de>"
+    ))]
     pub struct UnlimitedRenda<T: AdditionalInfo> {
         pub size: NoteSize,
         pub info: T::UnlimitedRenda,
@@ -121,7 +327,11 @@ pub mod typed {
         }
     }
 
-    #[derive(Clone, Copy, Debug)]
+    #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(bound(
+        serialize = "T::QuotaRenda: Serialize",
+        deserialize = "T::QuotaRenda: Deserialize<'de>"
+    ))]
     pub struct QuotaRenda<T: AdditionalInfo> {
         pub kind: QuotaRendaKind,
         pub quota: u64,
@@ -150,7 +360,11 @@ pub mod typed {
         }
     }
 
-    #[derive(Clone, Copy, Debug)]
+    #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(bound(
+        serialize = "T::Branch: Serialize",
+        deserialize = "T::Branch: Deserialize<'de>"
+    ))]
     pub struct Branch<T: AdditionalInfo> {
         pub judge_time: f64,
         pub switch_time: f64,
@@ -183,31 +397,71 @@ pub enum Level {
     Oni,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct SingleNoteKind {
     pub color: NoteColor,
     pub size: NoteSize,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum NoteColor {
     Don,
     Ka,
 }
 
-#[derive(Clone, Copy, Debug)]
+/// Either side of the drum, hit by the left or the right hand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enum)]
+pub enum DrumSide {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum NoteSize {
     Small,
     Large,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum QuotaRendaKind {
     Balloon,
     Potato,
 }
 
-#[derive(Clone, Copy, Debug)]
+impl fmt::Display for SingleNoteKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.color, self.size)
+    }
+}
+
+impl fmt::Display for NoteColor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            NoteColor::Don => "Don",
+            NoteColor::Ka => "Ka",
+        })
+    }
+}
+
+impl fmt::Display for NoteSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            NoteSize::Small => "Small",
+            NoteSize::Large => "Large",
+        })
+    }
+}
+
+impl fmt::Display for QuotaRendaKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            QuotaRendaKind::Balloon => "Balloon",
+            QuotaRendaKind::Potato => "Potato",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum BranchCondition {
     Pass,
     Renda(i64, i64),
@@ -218,7 +472,7 @@ pub enum BranchCondition {
 #[derive(Clone, Copy, Debug)]
 pub struct Measure(pub f64, pub f64);
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct BarLine {
     pub time: f64,
     pub scroll_speed: Bpm,
@@ -227,7 +481,7 @@ pub struct BarLine {
     pub branch: Option<BranchType>,
 }
 
-#[derive(Clone, Copy, Debug, Enum)]
+#[derive(Clone, Copy, Debug, PartialEq, Enum, Serialize, Deserialize)]
 pub enum BarLineKind {
     Normal,
     Branch,
@@ -245,7 +499,7 @@ impl Measure {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Bpm(pub f64);
 
 impl Bpm {
@@ -254,7 +508,7 @@ impl Bpm {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Enum)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enum, Serialize, Deserialize)]
 pub enum BranchType {
     Normal,
     Expert,
@@ -300,18 +554,32 @@ impl BranchType {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct BranchEvent {
     pub time: f64,
     pub kind: BranchEventKind,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum BranchEventKind {
     LevelHold(BranchType),
     Section,
 }
 
+/// A go-go time from `#GOGOSTART` to `#GOGOEND`, during which notes are worth more points.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GogoRange {
+    pub start_time: f64,
+    pub end_time: f64,
+    pub branch: Option<BranchType>,
+}
+
+impl GogoRange {
+    pub fn contains(&self, time: f64) -> bool {
+        self.start_time <= time && time < self.end_time
+    }
+}
+
 macro_rules! define_types {
     ($ty: ty) => {
         pub type Score = $crate::structs::typed::Score<$ty>;
@@ -329,3 +597,306 @@ macro_rules! define_types {
 pub mod just {
     define_types!(());
 }
+
+#[derive(Serialize, Deserialize)]
+struct ScoreCache<S> {
+    /// The modification time of the source file when the cache was written
+    source_modified: SystemTime,
+    score: S,
+}
+
+impl typed::Score<()> {
+    /// Write this score to `cache_path`, tagged with the modification time of `source_path`.
+    pub fn cache_to<P, Q>(&self, cache_path: P, source_path: Q) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let cache = ScoreCache {
+            source_modified: fs::metadata(source_path)?.modified()?,
+            score: self,
+        };
+        let file = BufWriter::new(File::create(cache_path)?);
+        serde_json::to_writer(file, &cache).map_err(Into::into)
+    }
+
+    /// Load the score written by `cache_to`.
+    /// Returns `None` if the cache is unavailable or `source_path` has been modified since.
+    pub fn load_cache<P, Q>(cache_path: P, source_path: Q) -> Option<Self>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let source_modified = fs::metadata(source_path).ok()?.modified().ok()?;
+        let file = BufReader::new(File::open(cache_path).ok()?);
+        let cache: ScoreCache<Self> = serde_json::from_reader(file).ok()?;
+        (cache.source_modified == source_modified).then_some(cache.score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::typed::{ScoreEvent, ScoreEventKind};
+    use super::{
+        just, Bpm, BranchCondition, BranchEventKind, BranchType, NoteColor, NoteSize,
+        QuotaRendaKind, SingleNoteKind,
+    };
+    use crate::score_builder::ScoreBuilder;
+
+    #[test]
+    fn test_measure_index() {
+        let score = ScoreBuilder::new()
+            .bar_line(0.0)
+            .branch(Some(BranchType::Normal))
+            .bar_line(2.0)
+            .bar_line(4.0)
+            .branch(Some(BranchType::Master))
+            .bar_line(2.0)
+            .bar_line(3.0)
+            .branch(None)
+            .bar_line(6.0)
+            .build();
+        let normal = BranchType::Normal;
+        assert_eq!(score.measure_index(-1.0, normal), None);
+        assert_eq!(score.measure_index(0.0, normal), Some(0));
+        assert_eq!(score.measure_index(1.0, normal), Some(0));
+        assert_eq!(score.measure_index(3.0, normal), Some(1));
+        assert_eq!(score.measure_index(5.0, normal), Some(2));
+        assert_eq!(score.measure_index(100.0, normal), Some(3));
+
+        let master = BranchType::Master;
+        assert_eq!(score.measure_index(3.5, master), Some(2));
+        assert_eq!(score.measure_index(6.0, master), Some(3));
+    }
+
+    #[test]
+    fn test_events() {
+        let score = ScoreBuilder::new()
+            .branch_point(3.0, 4.0, BranchCondition::Precision(50.0, 90.0))
+            .branch_point(7.0, 8.0, BranchCondition::Precision(50.0, 90.0))
+            .branch_event(2.0, BranchEventKind::Section)
+            .branch_event(6.0, BranchEventKind::LevelHold(BranchType::Master))
+            .branch_event(7.5, BranchEventKind::Section)
+            .gogo(1.0, 5.0)
+            .branch(Some(BranchType::Normal))
+            .gogo(5.0, 9.0)
+            .branch(Some(BranchType::Master))
+            .gogo(5.5, 6.5)
+            .build();
+        let events = score.events().collect::<Vec<_>>();
+        assert!(events.windows(2).all(|w| w[0].time <= w[1].time));
+        let times = events.iter().map(|e| e.time).collect::<Vec<_>>();
+        assert_eq!(
+            times,
+            vec![1.0, 2.0, 4.0, 5.0, 5.0, 5.5, 6.0, 6.5, 7.5, 8.0, 9.0]
+        );
+        assert!(matches!(
+            events[..5],
+            [
+                ScoreEvent {
+                    kind: ScoreEventKind::GogoStart(..),
+                    ..
+                },
+                ScoreEvent {
+                    kind: ScoreEventKind::BranchEvent(BranchEventKind::Section),
+                    ..
+                },
+                ScoreEvent {
+                    kind: ScoreEventKind::Branch(..),
+                    ..
+                },
+                ScoreEvent {
+                    kind: ScoreEventKind::GogoEnd(..),
+                    ..
+                },
+                ScoreEvent {
+                    kind: ScoreEventKind::GogoStart(..),
+                    ..
+                },
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_time_range() {
+        let score = just::Score::default();
+        assert_eq!(score.time_range(), None);
+        assert!(score.first_note().is_none());
+
+        let score = ScoreBuilder::new()
+            .don(1.0)
+            .renda(3.0, 7.5, NoteSize::Small)
+            .don(0.5)
+            .don(4.0)
+            .bar_line(0.0)
+            .bar_line(2.0)
+            .bar_line(4.0)
+            .build();
+        assert_eq!(score.time_range(), Some((0.0, 7.5)));
+        assert_eq!(score.first_note().map(|n| n.time), Some(0.5));
+        assert_eq!(score.last_note().map(|n| n.time), Some(4.0));
+    }
+
+    #[test]
+    fn test_offbeat_notes() {
+        // 16th notes, 24th notes, 48th notes, a note off by 5 ms, and one after the last bar
+        let score = ScoreBuilder::new()
+            .don(0.0)
+            .don(0.125)
+            .don(2.0 + 1.0 / 12.0)
+            .don(2.0 + 1.0 / 24.0)
+            .don(4.505)
+            .don(6.5)
+            .bar_line(0.0)
+            .bar_line(2.0)
+            .bar_line(4.0)
+            .bar_line(6.0)
+            .build();
+        let times = |tolerance| {
+            score
+                .offbeat_notes(tolerance)
+                .iter()
+                .map(|n| n.time)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(times(1e-3), vec![4.505]);
+        assert!(times(0.02).is_empty());
+    }
+
+    #[test]
+    fn test_score_serde_round_trip() {
+        let score = ScoreBuilder::new()
+            .bar_line(0.0)
+            .scroll_speed(150.0)
+            .branch(Some(BranchType::Master))
+            .large_ka(1.0)
+            .branch(None)
+            .balloon(2.0, 3.0, 5)
+            .build();
+        let json = serde_json::to_string(&score).unwrap();
+        let loaded = serde_json::from_str::<just::Score>(&json).unwrap();
+        assert_eq!(loaded, score);
+    }
+
+    #[test]
+    fn test_score_cache() {
+        let score = ScoreBuilder::new().bar_line(0.0).don(1.0).build();
+
+        let dir = std::env::temp_dir().join(format!("taiko-score-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source_path = dir.join("song.tja");
+        let cache_path = dir.join("song.json");
+        std::fs::write(&source_path, "").unwrap();
+
+        score.cache_to(&cache_path, &source_path).unwrap();
+        let loaded = just::Score::load_cache(&cache_path, &source_path);
+        assert_eq!(loaded, Some(score));
+
+        // Modifying the source invalidates the cache
+        let source = std::fs::File::options()
+            .write(true)
+            .open(&source_path)
+            .unwrap();
+        let modified = source.metadata().unwrap().modified().unwrap();
+        source
+            .set_modified(modified + std::time::Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(just::Score::load_cache(&cache_path, &source_path), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_note_kind_display() {
+        let kind = SingleNoteKind {
+            color: NoteColor::Don,
+            size: NoteSize::Small,
+        };
+        assert_eq!(kind.to_string(), "Don/Small");
+        let kind = SingleNoteKind {
+            color: NoteColor::Ka,
+            size: NoteSize::Large,
+        };
+        assert_eq!(kind.to_string(), "Ka/Large");
+        assert_eq!(QuotaRendaKind::Balloon.to_string(), "Balloon");
+        assert_eq!(QuotaRendaKind::Potato.to_string(), "Potato");
+    }
+
+    #[derive(Clone, Debug, Default)]
+    struct Numbered;
+
+    impl super::typed::AdditionalInfo for Numbered {
+        type Note = usize;
+        type SingleNote = usize;
+        type RendaContent = usize;
+        type UnlimitedRenda = usize;
+        type QuotaRenda = usize;
+        type Branch = usize;
+    }
+
+    #[test]
+    fn test_to_just() {
+        use super::typed::UnlimitedRenda;
+        use super::typed::{Note, NoteContent, RendaContent, RendaKind, Score, SingleNote};
+
+        let single = Note::<Numbered> {
+            scroll_speed: Bpm(120.0),
+            time: 1.5,
+            content: NoteContent::Single(SingleNote {
+                kind: SingleNoteKind {
+                    color: NoteColor::Ka,
+                    size: NoteSize::Large,
+                },
+                info: 1,
+            }),
+            branch: Some(BranchType::Expert),
+            info: 2,
+        };
+        let renda = Note::<Numbered> {
+            scroll_speed: Bpm(120.0),
+            time: 2.0,
+            content: NoteContent::Renda(RendaContent {
+                kind: RendaKind::Unlimited(UnlimitedRenda {
+                    size: NoteSize::Small,
+                    info: 3,
+                }),
+                end_time: 2.5,
+                info: 4,
+            }),
+            branch: None,
+            info: 5,
+        };
+        let score = Score::<Numbered> {
+            notes: vec![single, renda],
+            bar_lines: ScoreBuilder::new().bar_line(0.0).build().bar_lines,
+            ..Default::default()
+        };
+
+        let just = score.to_just();
+        assert_eq!(just.bar_lines.len(), 1);
+        assert!(matches!(
+            just.notes[0],
+            just::Note {
+                time,
+                content: just::NoteContent::Single(just::SingleNote { kind, .. }),
+                branch: Some(BranchType::Expert),
+                ..
+            } if time == 1.5 && kind.color == NoteColor::Ka && kind.size == NoteSize::Large
+        ));
+        assert!(matches!(
+            just.notes[1],
+            just::Note {
+                time,
+                content: just::NoteContent::Renda(just::RendaContent {
+                    kind: just::RendaKind::Unlimited(just::UnlimitedRenda {
+                        size: NoteSize::Small,
+                        ..
+                    }),
+                    end_time,
+                    ..
+                }),
+                branch: None,
+                ..
+            } if time == 2.0 && end_time == 2.5
+        ));
+    }
+}
@@ -1,7 +1,7 @@
 use crate::structs::just::*;
 use crate::structs::*;
 use chardetng::EncodingDetector;
-use encoding_rs::Encoding;
+use encoding_rs::{Encoding, SHIFT_JIS, UTF_8, WINDOWS_1252};
 use enum_map::EnumMap;
 use itertools::Itertools;
 use once_cell::sync::Lazy;
@@ -11,7 +11,7 @@ use std::cmp::{max, min};
 use std::collections::VecDeque;
 use std::fs::File;
 use std::io;
-use std::io::{Error, Read};
+use std::io::{BufRead, BufReader, Error, Read};
 use std::path::{Path, PathBuf};
 
 #[derive(Debug)]
@@ -23,10 +23,6 @@ pub enum TjaError {
 
 #[derive(Debug)]
 pub enum DecodingError {
-    AnotherEncodingWasUsed {
-        detected: &'static Encoding,
-        used: &'static Encoding,
-    },
     MalformedByteSequenceFound(&'static Encoding),
 }
 
@@ -42,83 +38,246 @@ pub struct Song {
     pub subtitle: Option<Subtitle>,
     pub bpm: Bpm,
     pub wave: Option<PathBuf>,
+    /// Image shown behind the playfield in place of the default background
+    pub bg_image: Option<PathBuf>,
     pub offset: f64,
+    pub level: Option<u32>,
+    /// `COURSE` of the first score, which is the one loaded
+    pub course: Course,
     pub song_volume: u32,
     pub se_volume: u32,
     pub balloons: Vec<u64>,
 
     pub score: Option<Score>, // will later be Vec<Score>
+    /// Problems in the score that have been skipped over while parsing it
+    pub warnings: Vec<TjaWarning>,
 }
 
 impl Default for Song {
     fn default() -> Self {
-        let (title, subtitle, wave, offset, balloons, score) = Default::default();
+        let (title, subtitle, wave, bg_image, offset, level, course, balloons, score, warnings) =
+            Default::default();
         Self {
             title,
             subtitle,
             bpm: Bpm(120.0),
             wave,
+            bg_image,
             offset,
+            level,
+            course,
             song_volume: 100, // default value is not asserted to be true
             se_volume: 100,   // default value is not asserted to be true
             score,
             balloons,
+            warnings,
         }
     }
 }
 
-#[derive(Debug)]
+/// A problem found while parsing a score, which does not prevent the rest from being loaded
+#[derive(Clone, Debug, PartialEq)]
+pub struct TjaWarning {
+    /// 1-based line number in the file
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for TjaWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Print `message` as a warning at `line` and keep it in `warnings`.
+fn push_warning(warnings: &mut Vec<TjaWarning>, line: usize, message: String) {
+    let warning = TjaWarning { line, message };
+    eprintln!("Warning: {}", warning);
+    warnings.push(warning);
+}
+
+#[derive(Debug, PartialEq)]
 pub struct Subtitle {
-    #[allow(dead_code)]
-    text: String,
-    #[allow(dead_code)]
-    style: SubtitleStyle,
+    pub text: String,
+    pub style: SubtitleStyle,
 }
 
-#[derive(Debug)]
+/// How the subtitle is displayed, given by the prefix of `SUBTITLE`
+#[derive(Debug, PartialEq)]
 pub enum SubtitleStyle {
     Unspecified,
+    /// `--`: hidden during play
     Suppress,
+    /// `++`: shown during play
     Show,
 }
 
+impl Subtitle {
+    /// Returns the text to show during play, which is `None` if the style suppresses it.
+    pub fn text_in_game(&self) -> Option<&str> {
+        match self.style {
+            SubtitleStyle::Suppress => None,
+            SubtitleStyle::Unspecified | SubtitleStyle::Show => Some(&self.text),
+        }
+    }
+}
+
 pub fn load_tja_from_file<P: AsRef<Path>>(path: P) -> Result<Song, TjaError> {
     let path = path.as_ref();
+    let mut song = load_tja_from_str(read_tja_source(path)?)?;
+    resolve_song_paths(&mut song, path);
+    Ok(song)
+}
+
+/// The metadata of a song needed to list it, without its score.
+#[derive(Debug)]
+pub struct SongHeader {
+    pub title: Option<String>,
+    pub subtitle: Option<Subtitle>,
+    pub bpm: Bpm,
+    pub wave: Option<PathBuf>,
+    pub offset: f64,
+    pub level: Option<u32>,
+    /// `COURSE` of the first score, which is the one loaded
+    pub course: Course,
+    /// The course of every score in the file, in order
+    pub courses: Vec<Course>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Course {
+    Easy,
+    Normal,
+    Hard,
+    Oni,
+    Edit,
+}
+
+/// A score without `COURSE` is Oni.
+impl Default for Course {
+    fn default() -> Self {
+        Course::Oni
+    }
+}
+
+impl Course {
+    /// Parse the value of `COURSE:`, either a name or a number from 0 to 4.
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "easy" | "0" => Some(Course::Easy),
+            "normal" | "1" => Some(Course::Normal),
+            "hard" | "2" => Some(Course::Hard),
+            "oni" | "3" => Some(Course::Oni),
+            "edit" | "4" => Some(Course::Edit),
+            _ => None,
+        }
+    }
+}
+
+/// Load the metadata before the first `#START` and the courses without parsing any score.
+/// This is much faster than `load_tja_from_file` for listing songs.
+pub fn load_tja_header<P: AsRef<Path>>(path: P) -> Result<SongHeader, TjaError> {
+    let path = path.as_ref();
+    let mut song = Song::default();
+    load_tja_metadata(&mut song, &mut read_tja_header_source(path)?.lines());
+    resolve_song_paths(&mut song, path);
+    Ok(SongHeader {
+        title: song.title,
+        subtitle: song.subtitle,
+        bpm: song.bpm,
+        wave: song.wave,
+        offset: song.offset,
+        level: song.level,
+        course: song.course,
+        courses: scan_courses(path)?,
+    })
+}
+
+/// Returns the course of every score in the file, looking only at the `COURSE:` and `#START`
+/// lines.  These are ASCII, so the file does not have to be decoded.
+fn scan_courses(path: &Path) -> Result<Vec<Course>, TjaError> {
+    let mut course = Course::default();
+    let mut courses = Vec::new();
+    for line in BufReader::new(File::open(path)?).split(b'\n') {
+        let line = line?;
+        if let Some(value) = line.strip_prefix(b"COURSE:") {
+            let value = std::str::from_utf8(value).ok().and_then(Course::parse);
+            course = value.unwrap_or(course);
+        } else if line.starts_with(b"#START") && courses.last() != Some(&course) {
+            // Both players of a double play share a course
+            courses.push(course);
+        }
+    }
+    Ok(courses)
+}
+
+/// The files specified in a tja file are relative to it.
+fn resolve_song_paths(song: &mut Song, path: &Path) {
+    if let Some(wave) = &song.wave {
+        song.wave = Some(path.with_file_name(wave));
+    }
+    if let Some(bg_image) = &song.bg_image {
+        song.bg_image = Some(path.with_file_name(bg_image));
+    }
+}
+
+fn read_tja_source(path: &Path) -> Result<String, TjaError> {
     let mut file = File::open(path)?;
     let mut buf = Vec::new();
     let _ = file.read_to_end(&mut buf)?;
+    decode_tja_source(&buf)
+}
+
+/// Same as `read_tja_source`, but stops reading after the first `#START` line.
+fn read_tja_header_source(path: &Path) -> Result<String, TjaError> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut buf = Vec::new();
+    loop {
+        let line_start = buf.len();
+        if file.read_until(b'\n', &mut buf)? == 0 || buf[line_start..].starts_with(b"#START") {
+            break;
+        }
+    }
+    decode_tja_source(&buf)
+}
+
+/// Encodings tried in order when the detected one cannot decode the source
+static FALLBACK_ENCODINGS: [&Encoding; 3] = [SHIFT_JIS, UTF_8, WINDOWS_1252];
 
+fn decode_tja_source(buf: &[u8]) -> Result<String, TjaError> {
     let mut detector = EncodingDetector::new();
-    detector.feed(&buf, true);
+    detector.feed(buf, true);
     let encoding = detector.guess(None, true);
 
-    let (source, actual_encoding, replacement) = encoding.decode(&buf);
-    if encoding != actual_encoding {
-        Err(TjaError::DecodingError(
-            DecodingError::AnotherEncodingWasUsed {
-                detected: encoding,
-                used: actual_encoding,
-            },
-        ))
-    } else if replacement {
-        Err(TjaError::DecodingError(
-            DecodingError::MalformedByteSequenceFound(encoding),
-        ))
-    } else {
-        let mut song = load_tja_from_str(source.to_string())?;
-        if let Some(wave) = song.wave {
-            song.wave = Some(path.with_file_name(wave));
+    // A BOM, if any, takes precedence over the detected encoding
+    let (source, _, replacement) = encoding.decode(buf);
+    if !replacement {
+        return Ok(source.into_owned());
+    }
+
+    // The BOM or the detection may be wrong, so try the usual encodings of tja files
+    let content = Encoding::for_bom(buf).map_or(buf, |(_, bom_length)| &buf[bom_length..]);
+    for &fallback in FALLBACK_ENCODINGS.iter() {
+        let (source, replacement) = fallback.decode_without_bom_handling(content);
+        if !replacement {
+            eprintln!(
+                "Warning: The file could not be decoded as {}, so {} was used instead",
+                encoding.name(),
+                fallback.name()
+            );
+            return Ok(source.into_owned());
         }
-        Ok(song)
     }
+    Err(TjaError::DecodingError(
+        DecodingError::MalformedByteSequenceFound(encoding),
+    ))
 }
 
 #[derive(Clone, Debug)]
 struct RendaBuffer(Bpm, f64, RendaContent);
 
 #[derive(Debug)]
-struct ScoreParser<'a> {
-    song: &'a Song,
+struct ScoreParser {
     score: Score,
 
     // elements buffer in current measure
@@ -128,6 +287,10 @@ struct ScoreParser<'a> {
     parser_state: ParserState,
 
     balloons: VecDeque<u64>,
+
+    /// The line being parsed, to which the warnings are attributed
+    line_number: usize,
+    warnings: Vec<TjaWarning>,
 }
 
 #[derive(Clone, Debug)]
@@ -171,12 +334,11 @@ struct SubsequentBranchContext {
     filled_branch: EnumMap<BranchType, bool>,
 }
 
-impl ScoreParser<'_> {
+impl ScoreParser {
     fn new(song: &Song, _player: Player) -> ScoreParser {
         let (score, elements, measure) = Default::default();
         // TODO store player etc. to score
         ScoreParser {
-            song,
             score,
             elements,
             branch_context: BranchContext::Outside,
@@ -193,15 +355,23 @@ impl ScoreParser<'_> {
                 first_measure_in_branch: false,
             },
             balloons: song.balloons.iter().copied().collect(),
+            line_number: 0,
+            warnings: Vec::new(),
         }
     }
 
+    fn warn(&mut self, message: String) {
+        push_warning(&mut self.warnings, self.line_number, message);
+    }
+
+    /// Parse the lines after `#START`, each given with its 1-based line number in the file.
     fn parse_lines<'a, I>(&mut self, lines: I) -> bool
     where
-        I: Iterator<Item = &'a str>,
+        I: Iterator<Item = (usize, &'a str)>,
     {
         let mut ended_with_end = false;
-        for line in lines {
+        for (line_number, line) in lines {
+            self.line_number = line_number;
             // TODO check if this parser is compatible
             let line = line
                 .split("//")
@@ -214,7 +384,7 @@ impl ScoreParser<'_> {
                 if let Some(bpm) = bpm.parse_first() {
                     self.elements.push(TjaElement::BpmChange(bpm));
                 } else {
-                    eprintln!("Parse error: {}", line);
+                    self.warn(format!("Parse error: {}", line));
                 }
             } else if line.starts_with("#GOGOSTART") {
                 self.elements.push(TjaElement::Gogo(true));
@@ -230,7 +400,7 @@ impl ScoreParser<'_> {
                 if let Some(scroll) = scroll.parse_first() {
                     self.elements.push(TjaElement::Scroll(scroll));
                 } else {
-                    println!("Ignored: {}", line);
+                    self.warn(format!("Ignored: {}", line));
                 }
             } else if let Some(delay) = line.strip_prefix("#DELAY") {
                 if let Some(delay) = delay.parse_first() {
@@ -256,26 +426,49 @@ impl ScoreParser<'_> {
             } else if line.starts_with("#BARLINEOFF") {
                 self.elements.push(TjaElement::BarLine(false));
             } else {
-                if line.starts_with('#') {
-                    eprintln!(
+                let command = line.starts_with('#');
+                if command {
+                    self.warn(format!(
                         "Command {} is not recognized. Parsing as score instead.",
                         line
-                    );
+                    ));
                 }
                 let mut split = line.split(',');
                 let line = split
                     .next()
                     .expect("split() returns always at least one element");
-                self.elements.extend(line.chars().filter_map(|c| match c {
-                    '0'..='9' => Some(TjaElement::NoteChar(c)),
-                    _ => None,
-                }));
+                let (elements, unknown_chars) = parse_note_chars(line);
+                // The characters of an unknown command have already been reported as a whole
+                if !command {
+                    for (column, c) in unknown_chars {
+                        self.warn(format!(
+                            "Unknown note {:?} at column {} is ignored",
+                            c, column
+                        ));
+                    }
+                }
+                self.elements.extend(elements);
                 if split.next().is_some() {
                     self.terminate_measure(true);
                 }
             }
         }
         self.score.notes.sort_by_key(|e| OrderedFloat::from(e.time));
+        // The branches are parsed one after another, but the bar lines are drawn by walking
+        // through the branch points in time order
+        self.score
+            .bar_lines
+            .sort_by_key(|b| OrderedFloat::from(b.time));
+        // A go-go time without #GOGOEND lasts until the end of the score
+        let time = self.parser_state.time;
+        for range in &mut self.score.gogo_ranges {
+            if range.end_time.is_infinite() {
+                range.end_time = time;
+            }
+        }
+        self.score
+            .gogo_ranges
+            .sort_by_key(|r| OrderedFloat::from(r.start_time));
         ended_with_end
     }
 
@@ -306,8 +499,13 @@ impl ScoreParser<'_> {
                 if context.measure_index < context.shared_elements.len() {
                     (true, false)
                 } else {
-                    eprintln!("Warning: the number of measures in this branch exceeded that of the first one.");
-                    eprintln!("The commands will be accepted, while the notes will be ignored.");
+                    push_warning(
+                        &mut self.warnings,
+                        self.line_number,
+                        "The number of measures in this branch exceeded that of the first one.  \
+                         The commands will be accepted, while the notes will be ignored."
+                            .to_owned(),
+                    );
                     (false, true)
                 }
             }
@@ -325,31 +523,13 @@ impl ScoreParser<'_> {
         let mut note_index = 0;
         let mut shared_elements_index = 0;
         for element in self.elements.iter() {
-            if let BranchContext::Subsequent(context) = &mut self.branch_context {
-                if let Some((total, shared_elements)) =
-                    &context.shared_elements.get(context.measure_index)
-                {
-                    for (_, element) in
-                        shared_elements[shared_elements_index..]
-                            .iter()
-                            .take_while(|(i, _)| {
-                                // i / total <= note_index / notes_count
-                                i.saturating_mul(notes_count) <= total.saturating_mul(note_index)
-                            })
-                    {
-                        // eprintln!("Foreign element {:?} applied", element);
-                        // TODO duplicate
-                        match element {
-                            TjaElement::BpmChange(bpm) => self.parser_state.bpm = Bpm(*bpm),
-                            TjaElement::Measure(a, b) => {
-                                self.parser_state.measure = Measure(*a, *b)
-                            }
-                            TjaElement::Delay(delay) => self.parser_state.time += delay,
-                            _ => {}
-                        }
-                        shared_elements_index += 1;
-                    }
-                }
+            if let BranchContext::Subsequent(context) = &self.branch_context {
+                Self::apply_shared_elements(
+                    &mut self.parser_state,
+                    context,
+                    &mut shared_elements_index,
+                    Some((note_index, notes_count)),
+                );
             }
             match element {
                 TjaElement::NoteChar(c) if parse_notes => {
@@ -376,7 +556,11 @@ impl ScoreParser<'_> {
                             None
                         }
                         '7' => {
-                            let quota = self.balloons.pop_front().unwrap_or(5);
+                            let quota = pop_balloon_quota(
+                                &mut self.balloons,
+                                &mut self.warnings,
+                                self.line_number,
+                            );
                             self.parser_state.renda =
                                 Some(self.renda(RendaKind::Quota(QuotaRenda {
                                     kind: QuotaRendaKind::Balloon,
@@ -390,7 +574,11 @@ impl ScoreParser<'_> {
                             Self::terminate_renda(&mut self.parser_state, branch)
                         }
                         '9' => {
-                            let quota = self.balloons.pop_front().unwrap_or(5);
+                            let quota = pop_balloon_quota(
+                                &mut self.balloons,
+                                &mut self.warnings,
+                                self.line_number,
+                            );
                             self.parser_state.renda =
                                 Some(self.renda(RendaKind::Quota(QuotaRenda {
                                     kind: QuotaRendaKind::Potato,
@@ -424,7 +612,18 @@ impl ScoreParser<'_> {
                         / notes_count as f64;
                 }
                 TjaElement::BpmChange(bpm) if parse_tempo => self.parser_state.bpm = Bpm(*bpm),
-                TjaElement::Gogo(gogo) => self.parser_state.gogo = *gogo,
+                TjaElement::Gogo(gogo) => {
+                    if *gogo != self.parser_state.gogo {
+                        let branch = self.current_branch();
+                        Self::switch_gogo(
+                            &mut self.score.gogo_ranges,
+                            *gogo,
+                            self.parser_state.time,
+                            branch,
+                        );
+                    }
+                    self.parser_state.gogo = *gogo;
+                }
                 TjaElement::Measure(a, b) if parse_tempo => {
                     self.parser_state.measure = Measure(*a, *b)
                 }
@@ -452,11 +651,52 @@ impl ScoreParser<'_> {
         }
 
         if let BranchContext::Subsequent(context) = &mut self.branch_context {
+            // The elements after the last note, which no element of this branch may follow
+            Self::apply_shared_elements(
+                &mut self.parser_state,
+                context,
+                &mut shared_elements_index,
+                None,
+            );
             context.measure_index += 1
         }
 
         self.elements.clear();
     }
+
+    /// Apply the tempo elements of the first branch to a subsequent one, from
+    /// `shared_elements_index` up to the position `note_index / notes_count` of the measure, or
+    /// to the end if `position` is `None`.
+    fn apply_shared_elements(
+        parser_state: &mut ParserState,
+        context: &SubsequentBranchContext,
+        shared_elements_index: &mut usize,
+        position: Option<(usize, usize)>,
+    ) {
+        let (total, shared_elements) = match context.shared_elements.get(context.measure_index) {
+            Some((total, shared_elements)) => (*total, shared_elements),
+            None => return,
+        };
+        for (_, element) in shared_elements[*shared_elements_index..]
+            .iter()
+            .take_while(|(i, _)| match position {
+                // i / total <= note_index / notes_count
+                Some((note_index, notes_count)) => {
+                    i.saturating_mul(notes_count) <= total.saturating_mul(note_index)
+                }
+                None => true,
+            })
+        {
+            // eprintln!("Foreign element {:?} applied", element);
+            match element {
+                TjaElement::BpmChange(bpm) => parser_state.bpm = Bpm(*bpm),
+                TjaElement::Measure(a, b) => parser_state.measure = Measure(*a, *b),
+                TjaElement::Delay(delay) => parser_state.time += delay,
+                _ => {}
+            }
+            *shared_elements_index += 1;
+        }
+    }
     fn scroll_speed(&self) -> Bpm {
         Bpm(self.parser_state.bpm.0 * self.parser_state.hs)
     }
@@ -558,20 +798,27 @@ impl ScoreParser<'_> {
         Ok(ret)
     }
 
+    /// The branch is judged at the start of the measure just before `#BRANCHSTART`, with the
+    /// hits made since the last `#SECTION` (or the start of the score).  The hits in that last
+    /// measure are not taken into account, and the branch switches at `#BRANCHSTART`.
     fn branch_start(&mut self, branch_condition: &str) {
-        // TODO start time
+        // Without a preceding measure, assume one of the current measure length
         let judge_time = self
             .score
             .bar_lines
             .last()
             .map(|b| b.time)
-            .unwrap_or_else(|| self.song.offset - self.song.bpm.beat_duration() * 4.0);
+            .unwrap_or_else(|| {
+                self.parser_state.time
+                    - self.parser_state.measure.get_beat_count()
+                        * self.parser_state.bpm.beat_duration()
+            });
         self.terminate_measure(false);
 
         let condition = match Self::parse_branch_condition(branch_condition) {
             Ok(c) => c,
             Err(..) => {
-                eprintln!("Invalid branch condition: {:?}", branch_condition);
+                self.warn(format!("Invalid branch condition: {:?}", branch_condition));
                 BranchCondition::Pass
             }
         };
@@ -585,7 +832,7 @@ impl ScoreParser<'_> {
         // println!("{} {}\n", judge_time, self.parser_state.time);
 
         if !matches!(self.branch_context, BranchContext::Outside) {
-            eprintln!("#BRANCHSTART was found before branch ends.");
+            self.warn("#BRANCHSTART was found before branch ends.".to_owned());
             self.branch_end(false);
         }
         self.branch_context = BranchContext::Started;
@@ -600,10 +847,10 @@ impl ScoreParser<'_> {
         let branch_context = std::mem::replace(&mut self.branch_context, BranchContext::Outside);
         self.branch_context = match branch_context {
             current @ BranchContext::Outside => {
-                eprintln!(
+                self.warn(format!(
                     "Cannot start branch {:?} outside #BRANCHSTART and END",
                     branch_type
-                );
+                ));
                 current
             }
             BranchContext::Started => BranchContext::First(FirstBranchContext {
@@ -653,10 +900,10 @@ impl ScoreParser<'_> {
 
         match std::mem::replace(&mut self.branch_context, BranchContext::Outside) {
             BranchContext::Outside => {
-                eprintln!("#BRANCHEND found before #BRANCHSTART");
+                self.warn("#BRANCHEND found before #BRANCHSTART".to_owned());
             }
             BranchContext::Started => {
-                eprintln!("Warning: None of #N, #E, #M was found between #BRANCHTSTART and END");
+                self.warn("None of #N, #E, #M was found between #BRANCHSTART and END".to_owned());
             }
             BranchContext::First(_) => {
                 // No need to restore parser_state
@@ -676,7 +923,7 @@ impl ScoreParser<'_> {
     fn level_hold(&mut self) {
         let branch_type = match &self.branch_context {
             BranchContext::Outside | BranchContext::Started => {
-                eprintln!("#LEVELHOLD before #N, #E or #M is ignored.");
+                self.warn("#LEVELHOLD before #N, #E or #M is ignored.".to_owned());
                 return;
             }
             BranchContext::First(context) => context.branch_type,
@@ -687,6 +934,28 @@ impl ScoreParser<'_> {
         self.push_branch_event(BranchEventKind::LevelHold(branch_type));
     }
 
+    /// Open a go-go time, or close the last open one that applies to `branch`.
+    fn switch_gogo(
+        gogo_ranges: &mut Vec<GogoRange>,
+        gogo: bool,
+        time: f64,
+        branch: Option<BranchType>,
+    ) {
+        if gogo {
+            gogo_ranges.push(GogoRange {
+                start_time: time,
+                end_time: f64::INFINITY,
+                branch,
+            });
+        } else if let Some(range) = gogo_ranges
+            .iter_mut()
+            .rev()
+            .find(|r| r.end_time.is_infinite() && (r.branch.is_none() || r.branch == branch))
+        {
+            range.end_time = time;
+        }
+    }
+
     fn push_branch_event(&mut self, kind: BranchEventKind) {
         self.score.branch_events.push(BranchEvent {
             time: self.parser_state.time,
@@ -695,6 +964,34 @@ impl ScoreParser<'_> {
     }
 }
 
+/// Returns the number of hits to burst the next balloon, which is 5 if `BALLOON` runs short.
+fn pop_balloon_quota(
+    balloons: &mut VecDeque<u64>,
+    warnings: &mut Vec<TjaWarning>,
+    line: usize,
+) -> u64 {
+    balloons.pop_front().unwrap_or_else(|| {
+        let message = "BALLOON has fewer counts than the balloons; 5 is used".to_owned();
+        push_warning(warnings, line, message);
+        5
+    })
+}
+
+/// Returns the notes in a line of the score, and the characters that are neither notes nor
+/// whitespace with their 1-based columns.
+fn parse_note_chars(line: &str) -> (Vec<TjaElement>, Vec<(usize, char)>) {
+    let mut elements = Vec::new();
+    let mut unknown_chars = Vec::new();
+    for (i, c) in line.chars().enumerate() {
+        match c {
+            '0'..='9' => elements.push(TjaElement::NoteChar(c)),
+            _ if c.is_whitespace() => {}
+            _ => unknown_chars.push((i + 1, c)),
+        }
+    }
+    (elements, unknown_chars)
+}
+
 #[derive(Clone, Debug)]
 enum TjaElement {
     NoteChar(char),
@@ -703,6 +1000,9 @@ enum TjaElement {
     Measure(f64, f64),
     Scroll(f64),
     Delay(f64),
+    /// `#BARLINEON` or `#BARLINEOFF`.  The bar line of a measure is decided when its first note
+    /// is parsed, so the command affects the measures starting after it; one placed after the
+    /// first note of a measure takes effect from the next measure.
     BarLine(bool),
 }
 
@@ -722,6 +1022,7 @@ impl Default for Player {
 pub fn load_tja_from_str(source: String) -> Result<Song, TjaError> {
     let mut song = Song::default();
 
+    let line_count = source.lines().count();
     let mut lines = source.lines();
     #[allow(clippy::never_loop)]
     loop {
@@ -731,10 +1032,18 @@ pub fn load_tja_from_str(source: String) -> Result<Song, TjaError> {
             Some(player) => player,
         };
         let mut song_context = ScoreParser::new(&song, player);
-        let ended_with_end = song_context.parse_lines(lines.by_ref());
+        let first_line_number = line_count - lines.clone().count() + 1;
+        let ended_with_end = song_context.parse_lines(
+            lines
+                .by_ref()
+                .enumerate()
+                .map(|(i, line)| (first_line_number + i, line)),
+        );
         song.score = Some(song_context.score);
+        song.warnings = song_context.warnings;
         if !ended_with_end {
-            eprintln!("Warning: The score did not ended with #END");
+            let message = "The score did not end with #END".to_owned();
+            push_warning(&mut song.warnings, line_count, message);
             break;
         }
         break;
@@ -780,8 +1089,8 @@ where
                     style: SubtitleStyle::Unspecified,
                 })
             }
-        } else if let Some(_level) = line.strip_prefix("LEVEL:") {
-            eprintln!("Warning: LEVEL not implemented");
+        } else if let Some(level) = line.strip_prefix("LEVEL:") {
+            song.level = level.parse_first();
         } else if let Some(bpm) = line.strip_prefix("BPM:") {
             // TODO error warnings and wider accepted format
             if let Some(bpm) = bpm.parse_first() {
@@ -791,6 +1100,11 @@ where
             }
         } else if let Some(wave) = line.strip_prefix("WAVE:") {
             song.wave = Some(Path::new(wave).to_path_buf());
+        } else if let Some(bg_image) = line.strip_prefix("BGIMAGE:") {
+            let bg_image = bg_image.trim();
+            song.bg_image = (!bg_image.is_empty()).then(|| PathBuf::from(bg_image));
+        } else if let Some(_) = line.strip_prefix("BGMOVIE:") {
+            eprintln!("Warning: BGMOVIE not implemented")
         } else if let Some(offset) = line.strip_prefix("OFFSET:") {
             if let Some(offset) = offset.parse_first() {
                 song.offset = offset;
@@ -812,8 +1126,9 @@ where
             eprintln!("Warning: SCOREINIT not implemented")
         } else if let Some(_) = line.strip_prefix("SCOREDIFF:") {
             eprintln!("Warning: SCOREDIFF not implemented")
-        } else if let Some(_) = line.strip_prefix("COURSE:") {
-            eprintln!("Warning: COURSE not implemented")
+        } else if let Some(course) = line.strip_prefix("COURSE:") {
+            // Only the first score is loaded, whatever its course is
+            song.course = Course::parse(course).unwrap_or(song.course);
         } else if let Some(_) = line.strip_prefix("STYLE:") {
             eprintln!("Warning: STYLE not implemented")
         } else if let Some(_) = line.strip_prefix("GAME:") {
@@ -897,7 +1212,49 @@ parse_integer!(u64 u32 i64);
 
 #[cfg(test)]
 mod tests {
-    use super::ParseFirst;
+    use super::{
+        decode_tja_source, load_tja_from_file, load_tja_from_str, load_tja_header,
+        parse_note_chars, Course, ParseFirst, SubtitleStyle, TjaWarning,
+    };
+    use crate::structs::BranchType;
+    use std::path::Path;
+
+    #[test]
+    fn test_subtitle_style() {
+        let subtitle = |source: &str| {
+            load_tja_from_str(format!("SUBTITLE:{}\n#START\n1,\n#END", source))
+                .unwrap()
+                .subtitle
+                .unwrap()
+        };
+        let suppressed = subtitle("--foo");
+        assert_eq!(suppressed.style, SubtitleStyle::Suppress);
+        assert_eq!(suppressed.text, "foo");
+        assert_eq!(suppressed.text_in_game(), None);
+
+        let shown = subtitle("++bar");
+        assert_eq!(shown.style, SubtitleStyle::Show);
+        assert_eq!(shown.text_in_game(), Some("bar"));
+
+        let unspecified = subtitle("baz");
+        assert_eq!(unspecified.style, SubtitleStyle::Unspecified);
+        assert_eq!(unspecified.text_in_game(), Some("baz"));
+    }
+
+    #[test]
+    fn test_decode_fallback() {
+        let source = "TITLE:夜に駆ける\nSUBTITLE:--YOASOBI\nWAVE:夜に駆ける.ogg\n";
+        let (shift_jis, _, _) = encoding_rs::SHIFT_JIS.encode(source);
+        assert_eq!(decode_tja_source(&shift_jis).unwrap(), source);
+
+        // Shift_JIS with a stray UTF-8 BOM, which used to be rejected
+        let with_bom = [&b"\xEF\xBB\xBF"[..], &shift_jis].concat();
+        assert_eq!(decode_tja_source(&with_bom).unwrap(), source);
+
+        let (utf_8, _, _) = encoding_rs::UTF_8.encode(source);
+        let with_bom = [&b"\xEF\xBB\xBF"[..], &utf_8].concat();
+        assert_eq!(decode_tja_source(&with_bom).unwrap(), source);
+    }
 
     #[test]
     #[allow(clippy::approx_constant)]
@@ -929,6 +1286,45 @@ mod tests {
         assert_eq!("  120 //180".parse_first(), Some(120.0));
     }
 
+    #[test]
+    fn test_parse_note_chars() {
+        let (elements, unknown_chars) = parse_note_chars("10A2 0\tB");
+        assert_eq!(elements.len(), 4);
+        assert_eq!(unknown_chars, vec![(3, 'A'), (8, 'B')]);
+
+        let (elements, unknown_chars) = parse_note_chars("  1020 ");
+        assert_eq!(elements.len(), 4);
+        assert!(unknown_chars.is_empty());
+    }
+
+    #[test]
+    fn test_warnings() {
+        let source = "TITLE:Song\nBALLOON:4\n#START\n1020,\n10A0,\n#FOO\n7008,\n7008,\n#END";
+        let song = load_tja_from_str(source.to_owned()).unwrap();
+        let warning = |line, message: &str| TjaWarning {
+            line,
+            message: message.to_owned(),
+        };
+        assert_eq!(
+            song.warnings,
+            vec![
+                warning(5, "Unknown note 'A' at column 3 is ignored"),
+                warning(
+                    6,
+                    "Command #FOO is not recognized. Parsing as score instead."
+                ),
+                warning(8, "BALLOON has fewer counts than the balloons; 5 is used"),
+            ]
+        );
+        assert_eq!(
+            song.warnings[0].to_string(),
+            "line 5: Unknown note 'A' at column 3 is ignored"
+        );
+
+        let song = load_tja_from_str("BALLOON:4\n#START\n7008,\n#END".to_owned()).unwrap();
+        assert!(song.warnings.is_empty());
+    }
+
     #[test]
     fn test_parse_u64() {
         assert_eq!("0".parse_first(), Some(0u64));
@@ -962,4 +1358,119 @@ mod tests {
         assert_eq!("  \t123e2".parse_first(), Some(123i64));
         assert_eq!("  \t123//456".parse_first(), Some(123i64));
     }
+
+    #[test]
+    fn test_bg_image() {
+        let song = load_tja_from_str("BGIMAGE:bg.png\n#START\n1,\n#END".to_owned()).unwrap();
+        assert_eq!(song.bg_image.as_deref(), Some(Path::new("bg.png")));
+        let song = load_tja_from_str("BGIMAGE:\n#START\n1,\n#END".to_owned()).unwrap();
+        assert_eq!(song.bg_image, None);
+    }
+
+    #[test]
+    fn test_header_matches_full_parse() {
+        let source = "TITLE:Song\nSUBTITLE:--Artist\nBPM:150\nWAVE:song.ogg\nOFFSET:-1.5\n\
+                      COURSE:Hard\nLEVEL:7\n#START\n1,\n#END\n\
+                      COURSE:Oni\nLEVEL:9\n#START\n1,\n#END\n\
+                      COURSE:4\n#START P1\n1,\n#END\n#START P2\n1,\n#END";
+        let dir = std::env::temp_dir().join(format!("taiko-tja-header-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("song.tja");
+        std::fs::write(&path, source).unwrap();
+        let header = load_tja_header(&path).unwrap();
+        let song = load_tja_from_file(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(header.title, song.title);
+        assert_eq!(header.subtitle, song.subtitle);
+        assert_eq!(header.bpm.0, song.bpm.0);
+        assert_eq!(header.wave, song.wave);
+        assert_eq!(header.wave, Some(dir.join("song.ogg")));
+        assert_eq!(header.offset, song.offset);
+        assert_eq!(header.level, song.level);
+        assert_eq!(header.level, Some(7));
+        // Only the first score is loaded
+        assert_eq!(header.course, song.course);
+        assert_eq!(header.course, Course::Hard);
+        assert_eq!(
+            header.courses,
+            vec![Course::Hard, Course::Oni, Course::Edit]
+        );
+    }
+
+    #[test]
+    fn test_bpm_change_in_branches() {
+        // The tempo changes in the middle, at the end, and at the start of a measure.
+        // Only the first branch has to specify them.
+        let first = "1111\n#BPMCHANGE 240\n1111,\n1111\n#BPMCHANGE 60\n,\n#MEASURE 2/4\n11,\n";
+        let others = "1111\n1111,\n1111\n,\n11,\n";
+        let source = format!(
+            "BPM:120\n#START\n1,\n#BRANCHSTART p,50,90\n#N\n{}#E\n{}#M\n{}#BRANCHEND\n1,\n#END",
+            first, first, others
+        );
+        let score = load_tja_from_str(source).unwrap().score.unwrap();
+        let times = |branch| {
+            score
+                .notes
+                .iter()
+                .filter(|note| note.branch == branch)
+                .map(|note| note.time)
+                .collect::<Vec<_>>()
+        };
+        let expected = vec![
+            2.0, 2.25, 2.5, 2.75, 3.0, 3.125, 3.25, 3.375, 3.5, 3.75, 4.0, 4.25, 4.5, 5.5,
+        ];
+        assert_eq!(times(Some(BranchType::Normal)), expected);
+        assert_eq!(times(Some(BranchType::Expert)), expected);
+        assert_eq!(times(Some(BranchType::Master)), expected);
+        assert_eq!(times(None), vec![0.0, 6.5]);
+    }
+
+    #[test]
+    fn test_scroll_in_branches() {
+        let source = "BPM:120\n#START\n1,\n#BRANCHSTART p,50,90\n#N\n1,\n1,\n#E\n1,\n1,\n\
+            #M\n#SCROLL 2\n1,\n#SCROLL 0.5\n1,\n#BRANCHEND\n1,\n#END";
+        let score = load_tja_from_str(source.to_owned()).unwrap().score.unwrap();
+        let speeds = |branch| {
+            score
+                .bar_lines
+                .iter()
+                .filter(|bar_line| bar_line.branch == branch)
+                .map(|bar_line| bar_line.scroll_speed.0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(speeds(Some(BranchType::Normal)), vec![120.0, 120.0]);
+        assert_eq!(speeds(Some(BranchType::Expert)), vec![120.0, 120.0]);
+        assert_eq!(speeds(Some(BranchType::Master)), vec![240.0, 60.0]);
+        assert_eq!(speeds(None), vec![120.0, 120.0]);
+        assert!(score
+            .bar_lines
+            .windows(2)
+            .all(|pair| pair[0].time <= pair[1].time));
+    }
+
+    #[test]
+    fn test_branch_at_start() {
+        // There is no measure to judge, so the branch is judged one measure before the start
+        let source = "OFFSET:1\nBPM:240\n#START\n#BRANCHSTART p,50,90\n#N\n1,\n#E\n1,\n\
+            #M\n1,\n#BRANCHEND\n#END";
+        let score = load_tja_from_str(source.to_owned()).unwrap().score.unwrap();
+        assert_eq!(score.branches[0].judge_time, -2.0);
+        assert_eq!(score.branches[0].switch_time, -1.0);
+    }
+
+    #[test]
+    fn test_bar_line_on_off() {
+        let song =
+            load_tja_from_str("#START\n1\n#BARLINEOFF\n1,\n1,\n#BARLINEON\n1,\n#END".to_owned())
+                .unwrap();
+        let visible = song
+            .score
+            .unwrap()
+            .bar_lines
+            .iter()
+            .map(|bar_line| bar_line.visible)
+            .collect::<Vec<_>>();
+        assert_eq!(visible, vec![true, false, true]);
+    }
 }
@@ -1,3 +1,5 @@
+use std::time::{Duration, Instant};
+
 pub trait OptionCompare<T> {
     fn content_equals(self, other: Option<T>) -> bool;
 }
@@ -8,9 +10,93 @@ impl<T: PartialEq> OptionCompare<T> for Option<T> {
     }
 }
 
+/// Returns the decimal digits of `n`, most significant first.  Zero yields `[0]`.
 pub fn to_digits(n: u64) -> Vec<u32> {
-    n.to_string()
-        .chars()
-        .map(|c| c.to_digit(10).unwrap())
-        .collect()
+    to_digits_padded(n, 10, 1)
+}
+
+/// Returns the digits of `n` in `base`, most significant first,
+/// padded with leading zeros to at least `min_len` digits.
+pub fn to_digits_padded(mut n: u64, base: u32, min_len: usize) -> Vec<u32> {
+    assert!(base >= 2, "base must be at least 2");
+    let mut digits = Vec::new();
+    while n > 0 || digits.len() < min_len.max(1) {
+        digits.push((n % base as u64) as u32);
+        n /= base as u64;
+    }
+    digits.reverse();
+    digits
+}
+
+/// Keeps the frame rate under `fps` by sleeping the rest of each frame, or does nothing if
+/// `fps` is `None`.  Together with vsync, the frame rate is limited by whichever is lower.
+pub struct FrameLimiter {
+    frame_duration: Option<Duration>,
+    last_frame: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new(fps: Option<f64>) -> Self {
+        FrameLimiter {
+            frame_duration: fps.map(|fps| Duration::from_secs_f64(1.0 / fps)),
+            last_frame: Instant::now(),
+        }
+    }
+
+    /// Sleep until a frame has passed since the last call.  A frame that took longer is not
+    /// made up for by the following ones.
+    pub fn wait(&mut self) {
+        if let Some(frame_duration) = self.frame_duration {
+            if let Some(duration) = remaining_sleep(frame_duration, self.last_frame.elapsed()) {
+                std::thread::sleep(duration);
+            }
+        }
+        self.last_frame = Instant::now();
+    }
+}
+
+/// Returns how long to sleep to make a frame last `frame_duration`, given the time already
+/// spent in it, or `None` if it has already taken long enough.
+pub fn remaining_sleep(frame_duration: Duration, elapsed: Duration) -> Option<Duration> {
+    frame_duration
+        .checked_sub(elapsed)
+        .filter(|duration| *duration > Duration::ZERO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{remaining_sleep, to_digits, to_digits_padded};
+    use std::time::Duration;
+
+    #[test]
+    fn test_to_digits() {
+        assert_eq!(to_digits(0), vec![0]);
+        assert_eq!(to_digits(7), vec![7]);
+        assert_eq!(to_digits(1230), vec![1, 2, 3, 0]);
+        assert_eq!(to_digits(u64::MAX).len(), 20);
+    }
+
+    #[test]
+    fn test_to_digits_padded() {
+        assert_eq!(to_digits_padded(0, 10, 0), vec![0]);
+        assert_eq!(to_digits_padded(42, 10, 6), vec![0, 0, 0, 0, 4, 2]);
+        assert_eq!(to_digits_padded(123456, 10, 3), vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(to_digits_padded(10, 2, 1), vec![1, 0, 1, 0]);
+        assert_eq!(to_digits_padded(255, 16, 4), vec![0, 0, 15, 15]);
+    }
+
+    #[test]
+    fn test_remaining_sleep() {
+        let frame = Duration::from_millis(16);
+        assert_eq!(
+            remaining_sleep(frame, Duration::from_millis(0)),
+            Some(frame)
+        );
+        assert_eq!(
+            remaining_sleep(frame, Duration::from_millis(10)),
+            Some(Duration::from_millis(6))
+        );
+        assert_eq!(remaining_sleep(frame, frame), None);
+        assert_eq!(remaining_sleep(frame, Duration::from_millis(40)), None);
+    }
 }
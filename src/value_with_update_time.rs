@@ -1,3 +1,4 @@
+use sdl2::pixels::Color;
 use std::time::Duration;
 use std::time::Instant;
 
@@ -68,18 +69,77 @@ impl<T: Copy> ValueWithUpdateTime<T> {
 //     }
 // }
 
-pub struct EasingF64Impl<F> {
-    old_value: f64,
-    value: ValueWithUpdateTime<f64>,
+/// Common easing curves, each of which maps `0.0` to `0.0` and `1.0` to `1.0`.
+#[derive(Clone, Copy, Debug)]
+pub enum Easing {
+    Linear,
+    EaseInOut,
+    EaseOutCubic,
+    EaseOutQuad,
+}
+
+impl Easing {
+    pub fn apply(self, x: f64) -> f64 {
+        match self {
+            Easing::Linear => x,
+            Easing::EaseInOut => {
+                if x < 0.5 {
+                    4.0 * x.powi(3)
+                } else {
+                    1.0 - (2.0 - 2.0 * x).powi(3) / 2.0
+                }
+            }
+            Easing::EaseOutCubic => 1.0 - (1.0 - x).powi(3),
+            Easing::EaseOutQuad => 1.0 - (1.0 - x).powi(2),
+        }
+    }
+
+    /// Returns the curve as a function, to be passed to `EasingF64Impl::new`.
+    pub fn into_fn(self) -> impl Fn(f64) -> f64 {
+        move |x| self.apply(x)
+    }
+}
+
+/// Types whose values can be interpolated linearly.
+pub trait Lerp {
+    /// Returns `self` if `t` is `0.0`, and `other` if `t` is `1.0`.
+    fn lerp(&self, other: &Self, t: f64) -> Self;
+}
+
+impl Lerp for f64 {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        other * t + self * (1.0 - t)
+    }
+}
+
+impl Lerp for Color {
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        let f = |x: u8, y: u8| (x as f64).lerp(&(y as f64), t).clamp(0.0, 255.0).round() as u8;
+        Color::RGBA(
+            f(self.r, other.r),
+            f(self.g, other.g),
+            f(self.b, other.b),
+            f(self.a, other.a),
+        )
+    }
+}
+
+/// A value that moves smoothly from the previous value to the new one whenever it is set.
+pub struct EasingImpl<T, F> {
+    old_value: T,
+    value: ValueWithUpdateTime<T>,
     easing_time: Duration,
     easing_function: F,
 }
 
-impl<F> EasingF64Impl<F>
+pub type EasingF64Impl<F> = EasingImpl<f64, F>;
+
+impl<T, F> EasingImpl<T, F>
 where
+    T: Lerp + Copy,
     F: Fn(f64) -> f64,
 {
-    pub fn new(value: f64, easing_time: Duration, easing_function: F) -> Self {
+    pub fn new(value: T, easing_time: Duration, easing_function: F) -> Self {
         Self {
             old_value: value,
             value: ValueWithUpdateTime::new(value),
@@ -87,6 +147,26 @@ where
             easing_function,
         }
     }
+
+    pub fn get(&self) -> T {
+        self.value.get()
+    }
+
+    pub fn get_eased(&self) -> T {
+        let t = self.value.duration_since_update().as_secs_f64() / self.easing_time.as_secs_f64();
+        self.get_eased_at(t)
+    }
+
+    /// Returns the eased value at the progress `t`, where `1.0` means the easing has finished.
+    fn get_eased_at(&self, t: f64) -> T {
+        let t = (self.easing_function)(t.clamp(0.0, 1.0));
+        self.old_value.lerp(&self.value.get(), t)
+    }
+
+    pub fn set(&mut self, value: T) {
+        self.old_value = self.get_eased();
+        self.value.set(value);
+    }
 }
 
 pub trait EasingF64 {
@@ -103,17 +183,54 @@ where
     F: Fn(f64) -> f64,
 {
     fn get(&self) -> f64 {
-        self.value.get()
+        EasingImpl::get(self)
     }
 
     fn get_eased(&self) -> f64 {
-        let t = self.value.duration_since_update().as_secs_f64() / self.easing_time.as_secs_f64();
-        let t = (self.easing_function)(t.clamp(0.0, 1.0));
-        self.value.get() * t + self.old_value * (1.0 - t)
+        EasingImpl::get_eased(self)
     }
 
     fn set(&mut self, value: f64) {
-        self.old_value = self.get_eased();
-        self.value.set(value);
+        EasingImpl::set(self, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Easing, EasingImpl};
+    use itertools::Itertools;
+    use sdl2::pixels::Color;
+    use std::time::Duration;
+
+    #[test]
+    fn test_easing_curves() {
+        for easing in [
+            Easing::Linear,
+            Easing::EaseInOut,
+            Easing::EaseOutCubic,
+            Easing::EaseOutQuad,
+        ] {
+            assert_eq!(easing.apply(0.0), 0.0, "{:?}", easing);
+            assert_eq!(easing.apply(1.0), 1.0, "{:?}", easing);
+            let monotonic = (0..=100)
+                .map(|i| easing.apply(i as f64 / 100.0))
+                .tuple_windows()
+                .all(|(a, b)| a <= b);
+            assert!(monotonic, "{:?}", easing);
+        }
+    }
+
+    #[test]
+    fn test_ease_color() {
+        let black = Color::RGBA(0, 0, 0, 255);
+        let white = Color::RGBA(255, 255, 255, 255);
+        let mut color = EasingImpl::new(black, Duration::from_secs(1), Easing::Linear.into_fn());
+        assert_eq!(color.get_eased(), black);
+        color.set(white);
+        assert_eq!(color.get(), white);
+        assert_eq!(color.get_eased_at(0.0), black);
+        assert_eq!(color.get_eased_at(0.5), Color::RGBA(128, 128, 128, 255));
+        assert_eq!(color.get_eased_at(1.0), white);
+        assert_eq!(color.get_eased_at(2.0), white);
     }
 }